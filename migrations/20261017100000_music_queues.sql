@@ -0,0 +1,10 @@
+DROP TABLE IF EXISTS "public".music_queues;
+-- ************************************** "public".music_queues
+CREATE TABLE IF NOT EXISTS "public".music_queues (
+  guild_id bigint NOT NULL,
+  voice_channel_id bigint NOT NULL,
+  text_channel_id bigint,
+  position bigint NOT NULL,
+  CONSTRAINT PK_music_queues PRIMARY KEY (guild_id),
+  CONSTRAINT music_queue_of FOREIGN KEY (guild_id) REFERENCES "public".guilds ("id")
+);
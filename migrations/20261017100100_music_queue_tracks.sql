@@ -0,0 +1,17 @@
+DROP TABLE IF EXISTS "public".music_queue_tracks;
+-- ************************************** "public".music_queue_tracks
+CREATE TABLE IF NOT EXISTS "public".music_queue_tracks (
+  guild_id bigint NOT NULL,
+  queue_position int NOT NULL,
+  track text NOT NULL,
+  title text NOT NULL,
+  author text NOT NULL,
+  identifier text NOT NULL,
+  uri text NOT NULL,
+  length bigint NOT NULL,
+  is_stream boolean NOT NULL,
+  is_seekable boolean NOT NULL,
+  requester bigint,
+  CONSTRAINT PK_music_queue_tracks PRIMARY KEY (guild_id, queue_position),
+  CONSTRAINT track_of FOREIGN KEY (guild_id) REFERENCES "public".music_queues (guild_id) ON DELETE CASCADE
+);
@@ -0,0 +1,8 @@
+DROP TABLE IF EXISTS "public".guild_settings;
+-- ************************************** "public".guild_settings
+CREATE TABLE IF NOT EXISTS "public".guild_settings (
+  guild_id bigint NOT NULL,
+  playlist_role_id bigint,
+  CONSTRAINT PK_guild_settings PRIMARY KEY (guild_id),
+  CONSTRAINT settings_of FOREIGN KEY (guild_id) REFERENCES "public".guilds ("id")
+);
@@ -0,0 +1,14 @@
+DROP TABLE IF EXISTS "public".playlists;
+-- ************************************** "public".playlists
+-- A playlist either belongs to a user or is shared by a whole guild
+CREATE TABLE IF NOT EXISTS "public".playlists (
+  id bigserial NOT NULL,
+  name text NOT NULL,
+  user_id bigint,
+  guild_id bigint,
+  CONSTRAINT PK_playlists PRIMARY KEY (id),
+  CONSTRAINT playlist_of FOREIGN KEY (guild_id) REFERENCES "public".guilds ("id"),
+  CONSTRAINT playlist_owner CHECK ((user_id IS NULL) <> (guild_id IS NULL))
+);
+CREATE UNIQUE INDEX user_playlists ON "public".playlists (user_id, name) WHERE user_id IS NOT NULL;
+CREATE UNIQUE INDEX guild_playlists ON "public".playlists (guild_id, name) WHERE guild_id IS NOT NULL;
@@ -0,0 +1,17 @@
+DROP TABLE IF EXISTS "public".playlist_tracks;
+-- ************************************** "public".playlist_tracks
+CREATE TABLE IF NOT EXISTS "public".playlist_tracks (
+  playlist_id bigint NOT NULL,
+  playlist_position int NOT NULL,
+  track text NOT NULL,
+  title text NOT NULL,
+  author text NOT NULL,
+  identifier text NOT NULL,
+  uri text NOT NULL,
+  length bigint NOT NULL,
+  is_stream boolean NOT NULL,
+  is_seekable boolean NOT NULL,
+  requester bigint,
+  CONSTRAINT PK_playlist_tracks PRIMARY KEY (playlist_id, playlist_position),
+  CONSTRAINT playlist_track_of FOREIGN KEY (playlist_id) REFERENCES "public".playlists ("id") ON DELETE CASCADE
+);
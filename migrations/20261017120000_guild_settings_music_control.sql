@@ -0,0 +1,3 @@
+ALTER TABLE "public".guild_settings
+  ADD COLUMN IF NOT EXISTS dj_role_id bigint,
+  ADD COLUMN IF NOT EXISTS vote_skip_percent integer NOT NULL DEFAULT 50;
@@ -0,0 +1,3 @@
+ALTER TABLE "public".guild_settings
+  ADD COLUMN IF NOT EXISTS idle_timeout_secs integer NOT NULL DEFAULT 900,
+  ADD COLUMN IF NOT EXISTS always_on boolean NOT NULL DEFAULT false;
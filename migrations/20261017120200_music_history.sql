@@ -0,0 +1,19 @@
+DROP TABLE IF EXISTS "public".music_history;
+-- ************************************** "public".music_history
+CREATE TABLE IF NOT EXISTS "public".music_history (
+  id bigserial NOT NULL,
+  guild_id bigint NOT NULL,
+  played_at timestamptz NOT NULL DEFAULT now(),
+  track text NOT NULL,
+  title text NOT NULL,
+  author text NOT NULL,
+  identifier text NOT NULL,
+  uri text NOT NULL,
+  length bigint NOT NULL,
+  is_stream boolean NOT NULL,
+  is_seekable boolean NOT NULL,
+  requester bigint,
+  CONSTRAINT PK_music_history PRIMARY KEY (id),
+  CONSTRAINT history_of FOREIGN KEY (guild_id) REFERENCES "public".guilds ("id") ON DELETE CASCADE
+);
+CREATE INDEX guild_music_history ON "public".music_history (guild_id, id);
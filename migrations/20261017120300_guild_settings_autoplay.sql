@@ -0,0 +1,2 @@
+ALTER TABLE "public".guild_settings
+  ADD COLUMN IF NOT EXISTS autoplay boolean NOT NULL DEFAULT false;
@@ -0,0 +1,6 @@
+-- Unset limits mean there is no limit
+ALTER TABLE "public".guild_settings
+  ADD COLUMN IF NOT EXISTS max_user_tracks integer,
+  ADD COLUMN IF NOT EXISTS max_track_length_secs integer,
+  ADD COLUMN IF NOT EXISTS max_playlist_size integer,
+  ADD COLUMN IF NOT EXISTS allow_streams boolean NOT NULL DEFAULT true;
@@ -0,0 +1,2 @@
+ALTER TABLE "public".guild_settings
+  ADD COLUMN IF NOT EXISTS fair_queue boolean NOT NULL DEFAULT false;
@@ -0,0 +1,14 @@
+DROP TABLE IF EXISTS "public".music_plays;
+-- ************************************** "public".music_plays
+CREATE TABLE IF NOT EXISTS "public".music_plays (
+  id bigserial NOT NULL,
+  guild_id bigint NOT NULL,
+  played_at timestamptz NOT NULL DEFAULT now(),
+  requester bigint,
+  uri text NOT NULL,
+  title text NOT NULL,
+  played_ms bigint NOT NULL,
+  CONSTRAINT PK_music_plays PRIMARY KEY (id),
+  CONSTRAINT played_in FOREIGN KEY (guild_id) REFERENCES "public".guilds ("id") ON DELETE CASCADE
+);
+CREATE INDEX guild_music_plays ON "public".music_plays (guild_id, played_at);
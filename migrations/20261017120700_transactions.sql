@@ -0,0 +1,13 @@
+DROP TABLE IF EXISTS "public".transactions;
+-- ************************************** "public".transactions
+CREATE TABLE IF NOT EXISTS "public".transactions (
+  id bigserial NOT NULL,
+  guild_id bigint NOT NULL,
+  member_id bigint NOT NULL,
+  delta bigint NOT NULL,
+  reason text NOT NULL,
+  created_at timestamptz NOT NULL DEFAULT now(),
+  CONSTRAINT PK_transactions PRIMARY KEY (id),
+  CONSTRAINT transaction_in FOREIGN KEY (guild_id) REFERENCES "public".guilds ("id") ON DELETE CASCADE
+);
+CREATE INDEX member_transactions ON "public".transactions (guild_id, member_id, id);
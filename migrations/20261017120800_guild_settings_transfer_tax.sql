@@ -0,0 +1,2 @@
+ALTER TABLE "public".guild_settings
+  ADD COLUMN IF NOT EXISTS transfer_tax_percent integer NOT NULL DEFAULT 0;
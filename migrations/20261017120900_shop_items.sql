@@ -0,0 +1,17 @@
+DROP TABLE IF EXISTS "public".shop_items;
+-- ************************************** "public".shop_items
+-- Items without stock can be bought without limit
+CREATE TABLE IF NOT EXISTS "public".shop_items (
+  id bigserial NOT NULL,
+  guild_id bigint NOT NULL,
+  name text NOT NULL,
+  price bigint NOT NULL,
+  stock int,
+  role_id bigint,
+  consumable boolean NOT NULL DEFAULT false,
+  CONSTRAINT PK_shop_items PRIMARY KEY (id),
+  CONSTRAINT shop_item_of FOREIGN KEY (guild_id) REFERENCES "public".guilds ("id") ON DELETE CASCADE,
+  CONSTRAINT shop_item_price CHECK (price >= 0),
+  CONSTRAINT shop_item_stock CHECK (stock >= 0)
+);
+CREATE UNIQUE INDEX guild_shop_items ON "public".shop_items (guild_id, lower(name));
@@ -0,0 +1,11 @@
+DROP TABLE IF EXISTS "public".inventory_items;
+-- ************************************** "public".inventory_items
+CREATE TABLE IF NOT EXISTS "public".inventory_items (
+  item_id bigint NOT NULL,
+  member_id bigint NOT NULL,
+  quantity int NOT NULL,
+  CONSTRAINT PK_inventory_items PRIMARY KEY (item_id, member_id),
+  CONSTRAINT inventory_item_of FOREIGN KEY (item_id) REFERENCES "public".shop_items ("id") ON DELETE CASCADE,
+  CONSTRAINT inventory_item_quantity CHECK (quantity >= 0)
+);
+CREATE INDEX member_inventory_items ON "public".inventory_items (member_id);
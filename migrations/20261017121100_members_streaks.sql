@@ -0,0 +1,3 @@
+ALTER TABLE "public".members
+  ADD COLUMN IF NOT EXISTS daily_streak integer NOT NULL DEFAULT 0,
+  ADD COLUMN IF NOT EXISTS last_weekly timestamptz NOT NULL DEFAULT 'epoch';
@@ -0,0 +1,3 @@
+ALTER TABLE "public".guild_settings
+  ADD COLUMN IF NOT EXISTS daily_amount bigint NOT NULL DEFAULT 1000,
+  ADD COLUMN IF NOT EXISTS daily_cooldown_secs integer NOT NULL DEFAULT 86400;
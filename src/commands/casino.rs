@@ -0,0 +1,769 @@
+use std::{fmt, time::Duration};
+
+use poise::serenity::{
+	builder::{CreateComponents, CreateEmbed},
+	model::{
+		interactions::{message_component::ButtonStyle, InteractionResponseType},
+		misc::Mentionable,
+		user::User,
+	},
+};
+use rand::{distributions::WeightedIndex, prelude::*};
+
+use crate::{
+	commands::economy::check_bet,
+	constants::{
+		BLACKJACK_TIMEOUT_SECS, COINFLIP_CHALLENGE_TIMEOUT_SECS, ROULETTE_RED_NUMBERS, SLOT_SYMBOLS,
+	},
+	data::PgPoolContainer,
+	database::Guild,
+	types::{Error, PoiseContext},
+	utils::discord::{guild_check, reply_embed, reply_embed_components, reply_plain, EMBED_COLOUR},
+};
+
+const SUITS: [&str; 4] = ["♠", "♥", "♦", "♣"];
+const RANKS: [&str; 13] = [
+	"A", "2", "3", "4", "5", "6", "7", "8", "9", "10", "J", "Q", "K",
+];
+
+#[derive(Clone, Copy)]
+struct Card {
+	rank: usize,
+	suit: usize,
+}
+
+impl Card {
+	fn value(self) -> u32 {
+		match self.rank {
+			0 => 11,
+			1..=8 => self.rank as u32 + 1,
+			_ => 10,
+		}
+	}
+}
+
+impl fmt::Display for Card {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "`{}{}`", RANKS[self.rank], SUITS[self.suit])
+	}
+}
+
+fn shuffled_deck() -> Vec<Card> {
+	let mut deck: Vec<Card> = (0..4)
+		.flat_map(|suit| (0..13).map(move |rank| Card { rank, suit }))
+		.collect();
+	deck.shuffle(&mut thread_rng());
+	deck
+}
+
+// Aces count as 1 instead of 11 for as long as the hand would bust otherwise
+fn hand_value(hand: &[Card]) -> u32 {
+	let mut value: u32 = hand.iter().map(|card| card.value()).sum();
+	let mut aces = hand.iter().filter(|card| card.rank == 0).count();
+	while value > 21 && aces > 0 {
+		value -= 10;
+		aces -= 1;
+	}
+	value
+}
+
+fn is_blackjack(hand: &[Card]) -> bool {
+	hand.len() == 2 && hand_value(hand) == 21
+}
+
+fn display_hand(hand: &[Card]) -> String {
+	hand.iter()
+		.map(Card::to_string)
+		.collect::<Vec<_>>()
+		.join(" ")
+}
+
+// The dealer's second card stays hidden until the player is done
+fn blackjack_embed<'a>(
+	e: &'a mut CreateEmbed,
+	player: &[Card],
+	dealer: &[Card],
+	result: Option<&str>,
+) -> &'a mut CreateEmbed {
+	let (dealer_hand, dealer_value) = match result {
+		Some(_) => (display_hand(dealer), hand_value(dealer).to_string()),
+		None => (format!("{} `??`", dealer[0]), "?".to_owned()),
+	};
+	e.colour(EMBED_COLOUR)
+		.title("Blackjack")
+		.field(
+			format!("Your hand ({})", hand_value(player)),
+			display_hand(player),
+			false,
+		)
+		.field(
+			format!("Dealer's hand ({})", dealer_value),
+			dealer_hand,
+			false,
+		);
+	if let Some(result) = result {
+		e.description(result);
+	}
+	e
+}
+
+fn blackjack_buttons(c: &mut CreateComponents) -> &mut CreateComponents {
+	c.create_action_row(|r| {
+		r.create_button(|b| {
+			b.custom_id("blackjack_hit")
+				.label("Hit")
+				.style(ButtonStyle::Primary)
+		})
+		.create_button(|b| {
+			b.custom_id("blackjack_stand")
+				.label("Stand")
+				.style(ButtonStyle::Secondary)
+		})
+	})
+}
+
+/// Play a round of blackjack against the dealer.
+///
+/// Get closer to 21 than the dealer without going over. Winning pays the bet,
+/// a blackjack pays 3 to 2 and the dealer stands on 17.
+///
+/// Usage: `blackjack 100`
+#[poise::command(slash_command, aliases("bj"))]
+pub async fn blackjack(
+	ctx: PoiseContext<'_>,
+	#[description = "Amount to bet"] coins: i64,
+) -> Result<(), Error> {
+	let guild = guild_check(ctx).await?;
+	let member_id = ctx.author().id;
+	let db = {
+		let data = ctx.discord().data.read().await;
+		data.get::<PgPoolContainer>().unwrap().clone()
+	};
+	let guild = Guild::new(&db, guild.id);
+	if !check_bet(ctx, &guild, member_id, coins).await? {
+		return Ok(());
+	}
+
+	// The bet is taken up front, so it can't be spent elsewhere during the game
+	let mut balance = match guild
+		.add_member_coins(member_id, -coins, "blackjack")
+		.await?
+	{
+		Some(balance) => balance,
+		None => {
+			reply_plain(ctx, "You don't have enough balance").await?;
+			return Ok(());
+		}
+	};
+
+	let mut deck = shuffled_deck();
+	let mut player = vec![deck.pop().unwrap(), deck.pop().unwrap()];
+	let mut dealer = vec![deck.pop().unwrap(), deck.pop().unwrap()];
+
+	// The bet is given back if the game breaks off before it can be settled
+	let game = async {
+		if is_blackjack(&player) || is_blackjack(&dealer) {
+			return Ok::<_, Error>((None, None));
+		}
+		let game_message = reply_embed_components(
+			ctx,
+			|e| blackjack_embed(e, &player, &dealer, None),
+			blackjack_buttons,
+		)
+		.await?
+		.message()
+		.await?;
+
+		let mut last_interaction = None;
+		while let Some(interaction) = game_message
+			.await_component_interaction(ctx.discord())
+			.author_id(member_id)
+			.timeout(Duration::from_secs(BLACKJACK_TIMEOUT_SECS))
+			.await
+		{
+			if interaction.data.custom_id == "blackjack_hit" {
+				player.push(deck.pop().unwrap());
+				if hand_value(&player) < 21 {
+					interaction
+						.create_interaction_response(ctx.discord(), |r| {
+							r.kind(InteractionResponseType::UpdateMessage)
+								.interaction_response_data(|d| {
+									d.create_embed(|e| blackjack_embed(e, &player, &dealer, None))
+								})
+						})
+						.await?;
+					continue;
+				}
+			}
+			last_interaction = Some(interaction);
+			break;
+		}
+
+		// Running out of time counts as standing
+		if hand_value(&player) <= 21 {
+			while hand_value(&dealer) < 17 {
+				dealer.push(deck.pop().unwrap());
+			}
+		}
+
+		Ok((last_interaction, Some(game_message)))
+	}
+	.await;
+	let (last_interaction, message) = match game {
+		Ok(game) => game,
+		Err(why) => {
+			guild
+				.add_member_coins(member_id, coins, "blackjack")
+				.await?;
+			return Err(why);
+		}
+	};
+
+	let player_value = hand_value(&player);
+	let dealer_value = hand_value(&dealer);
+	let (payout, result) = if is_blackjack(&player) && is_blackjack(&dealer) {
+		(coins, "You both have blackjack, it's a push")
+	} else if is_blackjack(&player) {
+		(coins + coins * 3 / 2, "Blackjack! You win")
+	} else if is_blackjack(&dealer) {
+		(0, "The dealer has blackjack, you lose")
+	} else if player_value > 21 {
+		(0, "Bust! You lose")
+	} else if dealer_value > 21 {
+		(coins * 2, "The dealer busts, you win")
+	} else if player_value > dealer_value {
+		(coins * 2, "You win")
+	} else if player_value == dealer_value {
+		(coins, "It's a push")
+	} else {
+		(0, "The dealer wins")
+	};
+	if payout > 0 {
+		if let Some(new_balance) = guild
+			.add_member_coins(member_id, payout, "blackjack")
+			.await?
+		{
+			balance = new_balance;
+		}
+	}
+	let change = match payout - coins {
+		0 => "you got your bet back".to_owned(),
+		net if net > 0 => format!("you gained {} cowoins", net),
+		net => format!("you lost {} cowoins", -net),
+	};
+	let result = format!("{}, {}\nYou have {} cowoins now", result, change, balance);
+
+	match (last_interaction, message) {
+		(Some(interaction), _) => {
+			interaction
+				.create_interaction_response(ctx.discord(), |r| {
+					r.kind(InteractionResponseType::UpdateMessage)
+						.interaction_response_data(|d| {
+							d.create_embed(|e| blackjack_embed(e, &player, &dealer, Some(&result)))
+								.components(|c| c)
+						})
+				})
+				.await?;
+		}
+		(None, Some(mut message)) => {
+			message
+				.edit(ctx.discord(), |m| {
+					m.embed(|e| blackjack_embed(e, &player, &dealer, Some(&result)))
+						.components(|c| c)
+				})
+				.await?;
+		}
+		(None, None) => {
+			reply_embed(ctx, |e| blackjack_embed(e, &player, &dealer, Some(&result))).await?;
+		}
+	}
+
+	Ok(())
+}
+
+/// Spin the slot machine.
+///
+/// Three of a kind pay out, the rarer the symbol the more. Two of a kind give
+/// the bet back.
+///
+/// Usage: `slots 100`
+#[poise::command(slash_command, aliases("slot"))]
+pub async fn slots(
+	ctx: PoiseContext<'_>,
+	#[description = "Amount to bet"] coins: i64,
+) -> Result<(), Error> {
+	let guild = guild_check(ctx).await?;
+	let member_id = ctx.author().id;
+	let data = ctx.discord().data.read().await;
+	let db = data.get::<PgPoolContainer>().unwrap();
+	let guild = Guild::new(db, guild.id);
+	if !check_bet(ctx, &guild, member_id, coins).await? {
+		return Ok(());
+	}
+
+	let dist = WeightedIndex::new(SLOT_SYMBOLS.iter().map(|(_, weight, _)| weight)).unwrap();
+	let reels: Vec<usize> = (0..3).map(|_| dist.sample(&mut thread_rng())).collect();
+	let (delta, response) = if reels[0] == reels[1] && reels[1] == reels[2] {
+		let multiplier = SLOT_SYMBOLS[reels[0]].2;
+		(
+			coins * multiplier,
+			format!("{}x you gained {} cowoins", multiplier, coins * multiplier),
+		)
+	} else if reels[0] == reels[1] || reels[1] == reels[2] || reels[0] == reels[2] {
+		(0, "Two of a kind, you got your bet back".to_owned())
+	} else {
+		(
+			-coins,
+			format!("You lost {} cowoins, try again next time", coins),
+		)
+	};
+
+	let new_balance = match guild.add_member_coins(member_id, delta, "slots").await? {
+		Some(new_balance) => new_balance,
+		None => {
+			reply_plain(ctx, "You don't have enough balance").await?;
+			return Ok(());
+		}
+	};
+	let reel = reels
+		.iter()
+		.map(|i| SLOT_SYMBOLS[*i].0)
+		.collect::<Vec<_>>()
+		.join(" | ");
+	reply_plain(
+		ctx,
+		format!(
+			"**[ {} ]**\n{}\nYou have {} cowoins now",
+			reel, response, new_balance
+		),
+	)
+	.await?;
+
+	Ok(())
+}
+
+fn parse_side(side: &str) -> Option<bool> {
+	match side.to_lowercase().as_str() {
+		"heads" | "head" | "h" => Some(true),
+		"tails" | "tail" | "t" => Some(false),
+		_ => None,
+	}
+}
+
+fn display_side(heads: bool) -> &'static str {
+	if heads {
+		"heads"
+	} else {
+		"tails"
+	}
+}
+
+/// Flip a coin, against the house or against someone else.
+///
+/// Mention someone to challenge them, they have to accept the bet and the
+/// winner takes both bets.
+///
+/// Usage: `coinflip heads 100` or `coinflip tails 100 @user`
+#[poise::command(slash_command, aliases("cf"))]
+pub async fn coinflip(
+	ctx: PoiseContext<'_>,
+	#[description = "Heads or tails"] side: String,
+	#[description = "Amount to bet"] coins: i64,
+	#[description = "Who to challenge"] user: Option<User>,
+) -> Result<(), Error> {
+	let guild = guild_check(ctx).await?;
+	let member_id = ctx.author().id;
+	let heads = match parse_side(&side) {
+		Some(heads) => heads,
+		None => {
+			reply_plain(ctx, "Pick either heads or tails").await?;
+			return Ok(());
+		}
+	};
+	let db = {
+		let data = ctx.discord().data.read().await;
+		data.get::<PgPoolContainer>().unwrap().clone()
+	};
+	let guild = Guild::new(&db, guild.id);
+	if !check_bet(ctx, &guild, member_id, coins).await? {
+		return Ok(());
+	}
+
+	let opponent = match user {
+		Some(opponent) => opponent,
+		None => {
+			let landed_heads = thread_rng().gen_bool(0.5);
+			let (delta, response) = if landed_heads == heads {
+				(coins, format!("You gained {} cowoins", coins))
+			} else {
+				(-coins, format!("You lost {} cowoins", coins))
+			};
+			let new_balance = match guild.add_member_coins(member_id, delta, "coinflip").await? {
+				Some(new_balance) => new_balance,
+				None => {
+					reply_plain(ctx, "You don't have enough balance").await?;
+					return Ok(());
+				}
+			};
+			reply_plain(
+				ctx,
+				format!(
+					"It landed on {}\n{}\nYou have {} cowoins now",
+					display_side(landed_heads),
+					response,
+					new_balance
+				),
+			)
+			.await?;
+			return Ok(());
+		}
+	};
+	if opponent.id == member_id || opponent.bot {
+		reply_plain(ctx, "You can't challenge them").await?;
+		return Ok(());
+	}
+
+	// The challenger's bet is held until the challenge is settled
+	if guild
+		.add_member_coins(member_id, -coins, "coinflip")
+		.await?
+		.is_none()
+	{
+		reply_plain(ctx, "You don't have enough balance").await?;
+		return Ok(());
+	}
+	let challenge = async {
+		let message = reply_embed_components(
+			ctx,
+			|e| {
+				e.title("Coinflip").description(format!(
+					"{} challenges {} to a coinflip for {} cowoins, picking {}",
+					ctx.author().mention(),
+					opponent.mention(),
+					coins,
+					display_side(heads)
+				))
+			},
+			|c| {
+				c.create_action_row(|r| {
+					r.create_button(|b| {
+						b.custom_id("coinflip_accept")
+							.label("Accept")
+							.style(ButtonStyle::Success)
+					})
+					.create_button(|b| {
+						b.custom_id("coinflip_decline")
+							.label("Decline")
+							.style(ButtonStyle::Secondary)
+					})
+				})
+			},
+		)
+		.await?
+		.message()
+		.await?;
+
+		let interaction = message
+			.await_component_interaction(ctx.discord())
+			.author_id(opponent.id)
+			.timeout(Duration::from_secs(COINFLIP_CHALLENGE_TIMEOUT_SECS))
+			.await;
+
+		Ok::<_, Error>((message, interaction))
+	}
+	.await;
+	let (mut message, interaction) = match challenge {
+		Ok(challenge) => challenge,
+		Err(why) => {
+			guild.add_member_coins(member_id, coins, "coinflip").await?;
+			return Err(why);
+		}
+	};
+	let accepted = interaction.as_ref().map_or(false, |interaction| {
+		interaction.data.custom_id == "coinflip_accept"
+	});
+
+	// The coin is only flipped once the challenge is accepted
+	let outcome = match accepted {
+		true => {
+			let landed_heads = thread_rng().gen_bool(0.5);
+			let winner = if landed_heads == heads {
+				member_id
+			} else {
+				opponent.id
+			};
+			guild
+				.settle_challenge(opponent.id, winner, coins, "coinflip")
+				.await?
+				.then(|| (landed_heads, winner))
+		}
+		false => None,
+	};
+	let result = match outcome {
+		Some((landed_heads, winner)) => format!(
+			"It landed on {}, {} wins {} cowoins",
+			display_side(landed_heads),
+			winner.mention(),
+			coins * 2
+		),
+		None => {
+			guild.add_member_coins(member_id, coins, "coinflip").await?;
+			match (&interaction, accepted) {
+				(_, true) => format!("{} can't afford the bet", opponent.mention()),
+				(Some(_), false) => format!("{} declined the challenge", opponent.mention()),
+				(None, false) => format!("{} didn't answer the challenge", opponent.mention()),
+			}
+		}
+	};
+
+	match interaction {
+		Some(interaction) => {
+			interaction
+				.create_interaction_response(ctx.discord(), |r| {
+					r.kind(InteractionResponseType::UpdateMessage)
+						.interaction_response_data(|d| {
+							d.create_embed(|e| {
+								e.colour(EMBED_COLOUR).title("Coinflip").description(result)
+							})
+							.components(|c| c)
+						})
+				})
+				.await?;
+		}
+		None => {
+			message
+				.edit(ctx.discord(), |m| {
+					m.embed(|e| e.colour(EMBED_COLOUR).title("Coinflip").description(result))
+						.components(|c| c)
+				})
+				.await?;
+		}
+	}
+
+	Ok(())
+}
+
+#[derive(Clone, Copy)]
+enum RouletteBet {
+	Number(u8),
+	Red,
+	Black,
+	Odd,
+	Even,
+	Low,
+	High,
+}
+
+impl RouletteBet {
+	fn parse(bet: &str) -> Option<Self> {
+		Some(match bet.to_lowercase().as_str() {
+			"red" => RouletteBet::Red,
+			"black" => RouletteBet::Black,
+			"odd" => RouletteBet::Odd,
+			"even" => RouletteBet::Even,
+			"low" => RouletteBet::Low,
+			"high" => RouletteBet::High,
+			number => match number.parse::<u8>() {
+				Ok(number) if number <= 36 => RouletteBet::Number(number),
+				_ => return None,
+			},
+		})
+	}
+
+	// How many times the bet a win pays, on top of the bet itself
+	fn multiplier(self) -> i64 {
+		match self {
+			RouletteBet::Number(_) => 35,
+			_ => 1,
+		}
+	}
+
+	// Zero loses every bet except the one on zero itself
+	fn wins(self, pocket: u8) -> bool {
+		match self {
+			RouletteBet::Number(number) => number == pocket,
+			_ if pocket == 0 => false,
+			RouletteBet::Red => ROULETTE_RED_NUMBERS.contains(&pocket),
+			RouletteBet::Black => !ROULETTE_RED_NUMBERS.contains(&pocket),
+			RouletteBet::Odd => pocket % 2 == 1,
+			RouletteBet::Even => pocket % 2 == 0,
+			RouletteBet::Low => pocket <= 18,
+			RouletteBet::High => pocket > 18,
+		}
+	}
+}
+
+fn pocket_colour(pocket: u8) -> &'static str {
+	if pocket == 0 {
+		"🟢"
+	} else if ROULETTE_RED_NUMBERS.contains(&pocket) {
+		"🔴"
+	} else {
+		"⚫"
+	}
+}
+
+/// Bet on where the roulette ball lands.
+///
+/// Bet on a number from 0 to 36 to win 35 times the bet, or on `red`,
+/// `black`, `odd`, `even`, `low` (1-18) or `high` (19-36) to double it.
+///
+/// Usage: `roulette red 100` or `roulette 17 100`
+#[poise::command(slash_command)]
+pub async fn roulette(
+	ctx: PoiseContext<'_>,
+	#[description = "A number, red, black, odd, even, low or high"] bet: String,
+	#[description = "Amount to bet"] coins: i64,
+) -> Result<(), Error> {
+	let guild = guild_check(ctx).await?;
+	let member_id = ctx.author().id;
+	let bet = match RouletteBet::parse(&bet) {
+		Some(bet) => bet,
+		None => {
+			reply_plain(
+				ctx,
+				"Bet on a number from 0 to 36, red, black, odd, even, low or high",
+			)
+			.await?;
+			return Ok(());
+		}
+	};
+	let data = ctx.discord().data.read().await;
+	let db = data.get::<PgPoolContainer>().unwrap();
+	let guild = Guild::new(db, guild.id);
+	if !check_bet(ctx, &guild, member_id, coins).await? {
+		return Ok(());
+	}
+
+	let pocket = thread_rng().gen_range(0..=36);
+	let (delta, response) = if bet.wins(pocket) {
+		let change = coins * bet.multiplier();
+		(change, format!("You gained {} cowoins", change))
+	} else {
+		(-coins, format!("You lost {} cowoins", coins))
+	};
+	let new_balance = match guild.add_member_coins(member_id, delta, "roulette").await? {
+		Some(new_balance) => new_balance,
+		None => {
+			reply_plain(ctx, "You don't have enough balance").await?;
+			return Ok(());
+		}
+	};
+	reply_plain(
+		ctx,
+		format!(
+			"The ball landed on {} {}\n{}\nYou have {} cowoins now",
+			pocket_colour(pocket),
+			pocket,
+			response,
+			new_balance
+		),
+	)
+	.await?;
+
+	Ok(())
+}
+
+/// Set the most cowoins that can be bet at once.
+///
+/// Leave out the amount to remove the limit.
+///
+/// Usage: `maxbet 5000`
+#[poise::command(
+	slash_command,
+	rename = "maxbet",
+	required_permissions = "MANAGE_GUILD"
+)]
+pub async fn max_bet(
+	ctx: PoiseContext<'_>,
+	#[description = "Most cowoins a bet can be"] coins: Option<u32>,
+) -> Result<(), Error> {
+	let guild = guild_check(ctx).await?;
+	{
+		let data = ctx.discord().data.read().await;
+		let db = data.get::<PgPoolContainer>().unwrap();
+		Guild::new(db, guild.id)
+			.set_max_bet(coins.map(|coins| coins as i64))
+			.await?;
+	}
+	let content = match coins {
+		Some(coins) => format!("Bets can be up to {} cowoins now", coins),
+		None => "Bets are no longer limited".to_owned(),
+	};
+	reply_plain(ctx, content).await?;
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn hand(ranks: &[usize]) -> Vec<Card> {
+		ranks.iter().map(|&rank| Card { rank, suit: 0 }).collect()
+	}
+
+	#[test]
+	fn counts_soft_aces_as_eleven() {
+		assert_eq!(hand_value(&hand(&[0, 5])), 17);
+		assert_eq!(hand_value(&hand(&[0, 12])), 21);
+		assert!(is_blackjack(&hand(&[0, 12])));
+	}
+
+	#[test]
+	fn counts_hard_aces_as_one() {
+		assert_eq!(hand_value(&hand(&[0, 5, 9])), 17);
+		assert_eq!(hand_value(&hand(&[0, 0])), 12);
+		assert_eq!(hand_value(&hand(&[0, 0, 0, 12])), 13);
+		assert_eq!(hand_value(&hand(&[9, 10, 11])), 30);
+		assert!(!is_blackjack(&hand(&[0, 5, 4])));
+	}
+
+	#[test]
+	fn pays_roulette_bets() {
+		assert_eq!(RouletteBet::Number(17).multiplier(), 35);
+		assert_eq!(RouletteBet::Red.multiplier(), 1);
+		assert!(RouletteBet::Number(0).wins(0));
+		assert!(!RouletteBet::Number(17).wins(18));
+		assert!(RouletteBet::Odd.wins(17));
+		assert!(RouletteBet::Even.wins(36));
+		assert!(RouletteBet::Low.wins(18));
+		assert!(RouletteBet::High.wins(19));
+		for bet in &[
+			RouletteBet::Red,
+			RouletteBet::Black,
+			RouletteBet::Odd,
+			RouletteBet::Even,
+			RouletteBet::Low,
+			RouletteBet::High,
+		] {
+			assert!(!bet.wins(0));
+		}
+	}
+
+	#[test]
+	fn colours_roulette_pockets() {
+		assert_eq!(pocket_colour(0), "🟢");
+		assert_eq!(pocket_colour(1), "🔴");
+		assert_eq!(pocket_colour(2), "⚫");
+		assert!(RouletteBet::Red.wins(1));
+		assert!(RouletteBet::Black.wins(2));
+		assert_eq!(
+			(1..=36)
+				.filter(|&pocket| RouletteBet::Red.wins(pocket))
+				.count(),
+			18
+		);
+	}
+
+	#[test]
+	fn parses_roulette_bets() {
+		assert!(matches!(RouletteBet::parse("RED"), Some(RouletteBet::Red)));
+		assert!(matches!(
+			RouletteBet::parse("36"),
+			Some(RouletteBet::Number(36))
+		));
+		assert!(RouletteBet::parse("37").is_none());
+		assert!(RouletteBet::parse("green").is_none());
+	}
+}
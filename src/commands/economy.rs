@@ -1,21 +1,64 @@
-use std::ops::Sub;
+use std::{ops::Sub, time::Duration};
 
 use chrono::prelude::*;
 use comfy_table::{Cell, CellAlignment::Center, ContentArrangement::Dynamic, Table};
-use poise;
+use poise::{
+	self,
+	serenity::model::{
+		id::UserId,
+		interactions::{message_component::ButtonStyle, InteractionResponseType},
+		misc::Mentionable,
+		user::User,
+	},
+};
 use rand::{distributions::WeightedIndex, prelude::*};
 
 use crate::{
-	constants::{DAILY_AMOUNT, GAMBLE_MULTIPLIERS, GAMBLE_WEIGHTS},
+	constants::{
+		DAILY_STREAK_BONUS_PERCENT, GAMBLE_MULTIPLIERS, GAMBLE_WEIGHTS,
+		MAX_DAILY_STREAK_BONUS_DAYS, PAY_CONFIRM_AMOUNT, PAY_CONFIRM_TIMEOUT_SECS,
+		TRANSACTIONS_LENGTH, WEEKLY_COOLDOWN_SECS, WEEKLY_MULTIPLIER,
+	},
 	data::PgPoolContainer,
 	database::Guild,
 	types::{Error, PoiseContext},
 	utils::{
-		discord::{guild_check, reply_embed, reply_plain},
+		discord::{guild_check, reply_embed, reply_embed_components, reply_plain},
 		helpers::format_seconds,
 	},
 };
 
+/// Checks whether the member can bet `coins`, and tells them why not if they
+/// can't.
+pub async fn check_bet(
+	ctx: PoiseContext<'_>,
+	guild: &Guild<'_>,
+	member_id: UserId,
+	coins: i64,
+) -> Result<bool, Error> {
+	if coins < 1 {
+		reply_plain(ctx, "Can't bet the given amount").await?;
+		return Ok(false);
+	}
+	if let Some(max_bet) = guild.get_settings().await?.max_bet {
+		if coins > max_bet {
+			reply_plain(ctx, format!("The max bet is {} cowoins", max_bet)).await?;
+			return Ok(false);
+		}
+	}
+	match guild.get_member(member_id).await? {
+		Some(member) if coins > member.coins => {
+			reply_plain(ctx, "You don't have enough balance").await?;
+			Ok(false)
+		}
+		Some(_) => Ok(true),
+		None => {
+			reply_plain(ctx, format!("Could not find user with id: {}", member_id)).await?;
+			Ok(false)
+		}
+	}
+}
+
 /// Check your current cowoins balance.
 #[poise::command(slash_command)]
 pub async fn balance(ctx: PoiseContext<'_>) -> Result<(), Error> {
@@ -44,88 +87,127 @@ pub async fn gamble(
 	let guild = guild_check(ctx).await?;
 	let guild_id = guild.id;
 	let member_id = ctx.author().id;
-	if coins < 1 {
-		reply_plain(ctx, "Can't gamble with given amount").await?;
-
-		return Ok(());
-	}
 
 	let data = ctx.discord().data.read().await;
 	let db = data.get::<PgPoolContainer>().unwrap();
 	let guild = Guild::new(db, guild_id);
-	if let Some(member) = guild.get_member(member_id).await? {
-		if coins > member.coins {
+	if !check_bet(ctx, &guild, member_id, coins).await? {
+		return Ok(());
+	}
+
+	let multipliers = GAMBLE_MULTIPLIERS;
+	let weights = GAMBLE_WEIGHTS;
+	let dist = WeightedIndex::new(&weights).unwrap();
+	let multiplier = multipliers[dist.sample(&mut thread_rng())];
+
+	let change = coins * multiplier;
+	let delta = match multiplier == 0 {
+		true => -coins,
+		false => change,
+	};
+
+	let response = match multiplier {
+		0 => format!("You lost {} cowoins, try again next time", coins),
+		1 => format!("1x you gained {} cowoins", change),
+		2 => format!("2x you gained {} cowoins", change),
+		3 => format!("3x you gained {} cowoins", change),
+		4 => format!("4x you gained {} cowoins", change),
+		5 => format!("5x GODLIKE!!!! you gained {} cowoins", change),
+		_ => {
+			reply_plain(ctx, "Something unexpected happned, try again later").await?;
+
+			return Ok(());
+		}
+	};
+	// The balance might have changed since it was checked above
+	let new_balance = match guild.add_member_coins(member_id, delta, "gamble").await? {
+		Some(new_balance) => new_balance,
+		None => {
 			reply_plain(ctx, "You don't have enough balance").await?;
 			return Ok(());
 		}
+	};
+	reply_plain(
+		ctx,
+		format!("{}\nYou have {} cowoins now", response, new_balance),
+	)
+	.await?;
 
-		let multipliers = GAMBLE_MULTIPLIERS;
-		let weights = GAMBLE_WEIGHTS;
-		let dist = WeightedIndex::new(&weights).unwrap();
-		let multiplier = multipliers[dist.sample(&mut thread_rng())];
-
-		let change = coins * multiplier;
-		let new_balance = match multiplier == 0 {
-			true => member.coins - coins,
-			false => member.coins + change,
-		};
-
-		let response = match multiplier {
-			0 => format!("You lost {} cowoins, try again next time", coins),
-			1 => format!("1x you gained {} cowoins", change),
-			2 => format!("2x you gained {} cowoins", change),
-			3 => format!("3x you gained {} cowoins", change),
-			4 => format!("4x you gained {} cowoins", change),
-			5 => format!("5x GODLIKE!!!! you gained {} cowoins", change),
-			_ => {
-				reply_plain(ctx, "Something unexpected happned, try again later").await?;
+	Ok(())
+}
 
-				return Ok(());
-			}
-		};
-		guild
-			.set_member_economy(member_id, new_balance, None)
-			.await?;
-		reply_plain(
-			ctx,
-			format!("{}\nYou have {} cowoins now", response, new_balance),
-		)
-		.await?;
+// Streaks count calendar days, or whole cooldowns if those are longer than a
+// day. A claim in the following period extends the streak, a claim in the
+// same period keeps it and anything later starts over.
+fn daily_streak(
+	streak: i32,
+	last_daily: DateTime<Utc>,
+	now: DateTime<Utc>,
+	cooldown_secs: i64,
+) -> i32 {
+	let period_days = (cooldown_secs / 86400).max(1);
+	let days = (now.naive_utc().date() - last_daily.naive_utc().date()).num_days();
+	if days < period_days {
+		streak.max(1)
+	} else if days <= period_days * 2 {
+		streak + 1
 	} else {
-		reply_plain(ctx, format!("Could not find user with id: {}", member_id)).await?;
+		1
 	}
+}
 
-	Ok(())
+fn daily_bonus(amount: i64, streak: i32) -> i64 {
+	amount * (streak - 1).clamp(0, MAX_DAILY_STREAK_BONUS_DAYS) as i64 * DAILY_STREAK_BONUS_PERCENT
+		/ 100
 }
 
 /// Grab your daily cowoins.
+///
+/// Claiming them day after day builds up a streak, which adds a bonus on top.
+/// Coming back too late resets the streak.
 #[poise::command(slash_command)]
 pub async fn daily(ctx: PoiseContext<'_>) -> Result<(), Error> {
-	let daily_const = DAILY_AMOUNT;
 	let guild = guild_check(ctx).await?;
 	let guild_id = guild.id;
 	let member_id = ctx.author().id;
 	let data = ctx.discord().data.read().await;
 	let db = data.get::<PgPoolContainer>().unwrap();
 	let guild = Guild::new(db, guild_id);
+	let settings = guild.get_settings().await?;
+	let cooldown = settings.daily_cooldown_secs as i64;
 	let content = {
 		match guild.get_member(member_id).await? {
 			Some(member) => {
 				let difference = Utc::now().sub(member.last_daily).num_seconds();
-				match difference > 86400 {
+				match difference > cooldown {
 					true => {
-						let new_balance = member.coins + daily_const;
-						guild
-							.set_member_economy(member_id, new_balance, Some(Utc::now()))
-							.await?;
-						format!(
-							"You have redeemed your daily {} cowoins, your balance is {}",
-							daily_const, new_balance
-						)
+						let streak = daily_streak(
+							member.daily_streak,
+							member.last_daily,
+							Utc::now(),
+							cooldown,
+						);
+						let bonus = daily_bonus(settings.daily_amount, streak);
+						let amount = settings.daily_amount + bonus;
+						match guild
+							.claim_daily(member_id, amount, streak, member.last_daily)
+							.await?
+						{
+							Some(new_balance) if bonus > 0 => format!(
+								"You have redeemed your daily {} cowoins, {} of them for your {} day streak, your balance is {}",
+								amount, bonus, streak, new_balance
+							),
+							Some(new_balance) => format!(
+								"You have redeemed your daily {} cowoins, your balance is {}",
+								amount, new_balance
+							),
+							// Another daily went through in the meantime
+							None => "You have already redeemed your daily cowoins".to_owned(),
+						}
 					}
 					false => format!(
 						"Wait another {} to redeem your daily cowoins",
-						format_seconds(86400 - difference as u64)
+						format_seconds((cooldown - difference) as u64)
 					),
 				}
 			}
@@ -139,6 +221,297 @@ pub async fn daily(ctx: PoiseContext<'_>) -> Result<(), Error> {
 	Ok(())
 }
 
+/// Grab your weekly cowoins, worth a few daily ones.
+#[poise::command(slash_command)]
+pub async fn weekly(ctx: PoiseContext<'_>) -> Result<(), Error> {
+	let guild = guild_check(ctx).await?;
+	let guild_id = guild.id;
+	let member_id = ctx.author().id;
+	let data = ctx.discord().data.read().await;
+	let db = data.get::<PgPoolContainer>().unwrap();
+	let guild = Guild::new(db, guild_id);
+	let amount = guild.get_settings().await?.daily_amount * WEEKLY_MULTIPLIER;
+	let content = match guild.get_member(member_id).await? {
+		Some(member) => {
+			let difference = Utc::now().sub(member.last_weekly).num_seconds();
+			match difference > WEEKLY_COOLDOWN_SECS {
+				true => match guild
+					.claim_weekly(member_id, amount, member.last_weekly)
+					.await?
+				{
+					Some(new_balance) => format!(
+						"You have redeemed your weekly {} cowoins, your balance is {}",
+						amount, new_balance
+					),
+					None => "You have already redeemed your weekly cowoins".to_owned(),
+				},
+				false => format!(
+					"Wait another {} to redeem your weekly cowoins",
+					format_seconds((WEEKLY_COOLDOWN_SECS - difference) as u64)
+				),
+			}
+		}
+		None => format!("Could not find user with id: {}", member_id),
+	};
+	reply_plain(ctx, content).await?;
+
+	Ok(())
+}
+
+/// Set how many cowoins `daily` gives and how often.
+///
+/// The weekly reward grows along with the daily one. Leave out the hours to
+/// keep the current cooldown.
+///
+/// Usage: `dailyreward 1000` or `dailyreward 500 12`
+#[poise::command(
+	slash_command,
+	rename = "dailyreward",
+	required_permissions = "MANAGE_GUILD"
+)]
+pub async fn daily_reward(
+	ctx: PoiseContext<'_>,
+	#[description = "Cowoins given by daily"] amount: u32,
+	#[description = "Hours between dailies, from 1 to 168"] hours: Option<u32>,
+) -> Result<(), Error> {
+	let guild = guild_check(ctx).await?;
+	if let Some(hours) = hours {
+		if !(1..=168).contains(&hours) {
+			reply_plain(ctx, "The cooldown must be between 1 and 168 hours").await?;
+			return Ok(());
+		}
+	}
+
+	let cooldown_secs = {
+		let data = ctx.discord().data.read().await;
+		let db = data.get::<PgPoolContainer>().unwrap();
+		let guild = Guild::new(db, guild.id);
+		let cooldown_secs = match hours {
+			Some(hours) => hours as i32 * 3600,
+			None => guild.get_settings().await?.daily_cooldown_secs,
+		};
+		guild.set_daily_reward(amount as i64, cooldown_secs).await?;
+		cooldown_secs
+	};
+	reply_plain(
+		ctx,
+		format!(
+			"Daily now gives {} cowoins every {}",
+			amount,
+			format_seconds(cooldown_secs as u64)
+		),
+	)
+	.await?;
+
+	Ok(())
+}
+
+/// Give some of your cowoins to someone else.
+///
+/// Large payments have to be confirmed first. Servers can take a tax on
+/// every payment, which the receiver pays.
+///
+/// Usage: `pay @user 100`
+#[poise::command(slash_command)]
+pub async fn pay(
+	ctx: PoiseContext<'_>,
+	#[description = "Who to pay"] user: User,
+	#[description = "Amount to pay"] coins: i64,
+) -> Result<(), Error> {
+	let guild = guild_check(ctx).await?;
+	let guild_id = guild.id;
+	let member_id = ctx.author().id;
+	if coins < 1 {
+		reply_plain(ctx, "Can't pay the given amount").await?;
+		return Ok(());
+	}
+	if user.id == member_id {
+		reply_plain(ctx, "You can't pay yourself").await?;
+		return Ok(());
+	}
+
+	let db = {
+		let data = ctx.discord().data.read().await;
+		data.get::<PgPoolContainer>().unwrap().clone()
+	};
+	let guild = Guild::new(&db, guild_id);
+	let member = match guild.get_member(member_id).await? {
+		Some(member) => member,
+		None => {
+			reply_plain(ctx, format!("Could not find user with id: {}", member_id)).await?;
+			return Ok(());
+		}
+	};
+	if guild.get_member(user.id).await?.is_none() {
+		reply_plain(ctx, format!("Could not find user with id: {}", user.id)).await?;
+		return Ok(());
+	}
+	if coins > member.coins {
+		reply_plain(ctx, "You don't have enough balance").await?;
+		return Ok(());
+	}
+
+	// Never more than the amount itself, but the product can overflow
+	let tax_percent = guild.get_settings().await?.transfer_tax_percent;
+	let tax = (coins as i128 * tax_percent as i128 / 100) as i64;
+	let summary = match tax {
+		0 => format!("{} cowoins to {}", coins, user.mention()),
+		_ => format!(
+			"{} cowoins to {}, who gets {} after {} cowoins of tax",
+			coins,
+			user.mention(),
+			coins - tax,
+			tax
+		),
+	};
+
+	if coins >= PAY_CONFIRM_AMOUNT {
+		let message = reply_embed_components(
+			ctx,
+			|e| {
+				e.title("Confirm Payment");
+				e.description(format!("Pay {}?", summary))
+			},
+			|c| {
+				c.create_action_row(|r| {
+					r.create_button(|b| {
+						b.custom_id("pay_confirm")
+							.label("Pay")
+							.style(ButtonStyle::Success)
+					})
+					.create_button(|b| {
+						b.custom_id("pay_cancel")
+							.label("Cancel")
+							.style(ButtonStyle::Secondary)
+					})
+				})
+			},
+		)
+		.await?
+		.message()
+		.await?;
+
+		let interaction = message
+			.await_component_interaction(ctx.discord())
+			.author_id(member_id)
+			.timeout(Duration::from_secs(PAY_CONFIRM_TIMEOUT_SECS))
+			.await;
+		let interaction = match interaction {
+			Some(interaction) => interaction,
+			None => {
+				message.edit(ctx.discord(), |m| m.components(|c| c)).await?;
+				reply_plain(ctx, "The payment was cancelled").await?;
+				return Ok(());
+			}
+		};
+		// Take the buttons away so the payment can't be confirmed twice
+		interaction
+			.create_interaction_response(ctx.discord(), |r| {
+				r.kind(InteractionResponseType::UpdateMessage)
+					.interaction_response_data(|d| d.components(|c| c))
+			})
+			.await?;
+		if interaction.data.custom_id != "pay_confirm" {
+			reply_plain(ctx, "The payment was cancelled").await?;
+			return Ok(());
+		}
+	}
+
+	// The balance might have changed since it was checked above
+	let new_balance = match guild
+		.transfer_member_coins(member_id, user.id, coins, tax)
+		.await?
+	{
+		Some(new_balance) => new_balance,
+		None => {
+			reply_plain(ctx, "You don't have enough balance").await?;
+			return Ok(());
+		}
+	};
+	reply_plain(
+		ctx,
+		format!("You paid {}\nYou have {} cowoins now", summary, new_balance),
+	)
+	.await?;
+
+	Ok(())
+}
+
+/// Set the tax taken from every payment between members.
+///
+/// Usage: `transfertax 5`
+#[poise::command(
+	slash_command,
+	rename = "transfertax",
+	required_permissions = "MANAGE_GUILD"
+)]
+pub async fn transfer_tax(
+	ctx: PoiseContext<'_>,
+	#[description = "Percentage taken from payments, from 0 to 100"] percent: u8,
+) -> Result<(), Error> {
+	let guild = guild_check(ctx).await?;
+	if percent > 100 {
+		reply_plain(ctx, "The percentage must be between 0 and 100").await?;
+		return Ok(());
+	}
+
+	{
+		let data = ctx.discord().data.read().await;
+		let db = data.get::<PgPoolContainer>().unwrap();
+		Guild::new(db, guild.id)
+			.set_transfer_tax(percent as i32)
+			.await?;
+	}
+	let content = match percent {
+		0 => "Payments are no longer taxed".to_owned(),
+		_ => format!("Payments are now taxed {}%", percent),
+	};
+	reply_plain(ctx, content).await?;
+
+	Ok(())
+}
+
+/// Show the latest changes to your cowoins balance.
+///
+/// Usage: `transactions` or `transactions @user`
+#[poise::command(slash_command)]
+pub async fn transactions(
+	ctx: PoiseContext<'_>,
+	#[description = "Whose transactions to show"] user: Option<User>,
+) -> Result<(), Error> {
+	let guild = guild_check(ctx).await?;
+	let user = user.as_ref().unwrap_or_else(|| ctx.author());
+	let data = ctx.discord().data.read().await;
+	let db = data.get::<PgPoolContainer>().unwrap();
+	let transactions = Guild::new(db, guild.id)
+		.get_transactions(user.id, TRANSACTIONS_LENGTH)
+		.await?;
+	if transactions.is_empty() {
+		reply_plain(ctx, format!("{} has no transactions yet", user.name)).await?;
+		return Ok(());
+	}
+
+	let desc = transactions
+		.iter()
+		.map(|transaction| {
+			format!(
+				"`{:+}` {} <t:{}:R>",
+				transaction.delta,
+				transaction.reason,
+				transaction.created_at.timestamp()
+			)
+		})
+		.collect::<Vec<_>>()
+		.join("\n");
+	reply_embed(ctx, |e| {
+		e.title(format!("Transactions of {}", user.name));
+		e.description(desc)
+	})
+	.await?;
+
+	Ok(())
+}
+
 /// Cowoins leaderboard.
 #[poise::command(slash_command)]
 pub async fn leaderboard(ctx: PoiseContext<'_>) -> Result<(), Error> {
@@ -176,3 +549,58 @@ pub async fn leaderboard(ctx: PoiseContext<'_>) -> Result<(), Error> {
 
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn extends_streak_on_the_next_day() {
+		let last = Utc.ymd(2021, 5, 1).and_hms(23, 0, 0);
+		assert_eq!(
+			daily_streak(3, last, Utc.ymd(2021, 5, 2).and_hms(23, 0, 0), 86400),
+			4
+		);
+		assert_eq!(
+			daily_streak(3, last, Utc.ymd(2021, 5, 3).and_hms(1, 0, 0), 86400),
+			4
+		);
+	}
+
+	#[test]
+	fn keeps_streak_on_the_same_day() {
+		let last = Utc.ymd(2021, 5, 1).and_hms(8, 0, 0);
+		let now = Utc.ymd(2021, 5, 1).and_hms(20, 0, 0);
+		assert_eq!(daily_streak(3, last, now, 3600), 3);
+		assert_eq!(daily_streak(0, last, now, 3600), 1);
+	}
+
+	#[test]
+	fn resets_streak_after_a_missed_period() {
+		let last = Utc.ymd(2021, 5, 1).and_hms(12, 0, 0);
+		assert_eq!(
+			daily_streak(3, last, Utc.ymd(2021, 5, 4).and_hms(0, 0, 0), 86400),
+			1
+		);
+		assert_eq!(
+			daily_streak(3, last, Utc.ymd(2021, 5, 16).and_hms(0, 0, 0), 7 * 86400),
+			1
+		);
+	}
+
+	#[test]
+	fn scales_streak_with_long_cooldowns() {
+		let last = Utc.ymd(2021, 5, 1).and_hms(12, 0, 0);
+		let now = Utc.ymd(2021, 5, 10).and_hms(12, 0, 0);
+		assert_eq!(daily_streak(3, last, now, 7 * 86400), 4);
+		assert_eq!(daily_streak(3, last, now, 86400), 1);
+	}
+
+	#[test]
+	fn caps_streak_bonus() {
+		assert_eq!(daily_bonus(1000, 1), 0);
+		assert_eq!(daily_bonus(1000, 4), 300);
+		assert_eq!(daily_bonus(1000, 50), 1000);
+		assert_eq!(daily_bonus(1000, 0), 0);
+	}
+}
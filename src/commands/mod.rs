@@ -1,6 +1,9 @@
+pub mod casino;
 pub mod economy;
 pub mod fun;
 pub mod meta;
 pub mod music;
+pub mod playlists;
 pub mod reddit;
 pub mod roleplay;
+pub mod shop;
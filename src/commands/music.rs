@@ -1,70 +1,56 @@
+use std::{str::FromStr, time::Duration};
+
 use anyhow::Context;
-use parse_duration::parse as parse_duration;
+use chrono::{Datelike, TimeZone, Utc};
+use lavalink_rs::model::{Track, TrackQueue, UserId as LavalinkUserId};
 use poise::{
     command,
-    serenity::model::{
-        guild::Guild,
-        id::{ChannelId, UserId},
-        misc::Mentionable,
+    serenity::{
+        builder::{CreateComponents, CreateEmbed},
+        model::{
+            guild::{Guild, Role},
+            id::{ChannelId, GuildId, RoleId, UserId},
+            interactions::{message_component::ButtonStyle, InteractionResponseType},
+            misc::Mentionable,
+            user::User,
+        },
     },
 };
+use rand::seq::SliceRandom;
 use tokio::time::Instant;
+use tracing::error;
 use url::Url;
 
 use crate::{
     constants::{
-        DESCRIPTION_LENGTH_CUTOFF, LIVE_INDICATOR, MAX_LIST_ENTRY_LENGTH, MAX_SINGLE_ENTRY_LENGTH,
-        UNKNOWN_TITLE,
+        DESCRIPTION_LENGTH_CUTOFF, LIVE_INDICATOR, MAX_LIST_ENTRY_LENGTH, MAX_PLAYLIST_FILE_SIZE,
+        MAX_PLAYLIST_FILE_TRACKS, MAX_SINGLE_ENTRY_LENGTH, MUSIC_STATS_LENGTH,
+        PLAYLIST_QUERY_PREFIX, QUEUE_PAGE_SIZE, QUEUE_PAGE_TIMEOUT_SECS, SEARCH_PICK_TIMEOUT_SECS,
+        SEARCH_RESULT_COUNT, SEEK_STEP_SECS, UNKNOWN_TITLE,
+    },
+    data::{
+        AlonePausedGuilds, FiltersMap, HistoryMap, IdleGuildMap, LastMessageMap, LoopModeMap,
+        NowPlayingMessageMap, PgPoolContainer, PlayerPositionMap, SkipVotesMap,
+    },
+    database::{self, GuildSettings, Playlists, StoredTrack},
+    lavalink::{
+        apply_filters, clear_player_state, enqueue, enqueue_front, estimate_position,
+        interleave_queued_tracks, join_internal, load_history, now_playing_embed, voice_listeners,
+        AudioFilters, EqPreset, LoopMode, NowPlayingMessage,
     },
-    data::{IdleGuildMap, LastMessageMap},
     types::{Error, PoiseContext},
     utils::{
-        discord::{guild_check, reply, reply_embed},
-        helpers::{chop_str, display_time_span, push_chopped_str},
+        discord::{
+            guild_check, reply, reply_embed, reply_embed_components, reply_file, EMBED_COLOUR,
+        },
+        helpers::{
+            chop_str, display_time_span, escape_str, format_seconds, parse_range, parse_seek_time,
+            parse_time, push_chopped_str, SeekTime,
+        },
+        playlist_files::{export_playlist, import_playlist, PlaylistFormat},
     },
 };
 
-async fn join_internal<G, C>(
-    ctx: &PoiseContext<'_>,
-    guild_id: G,
-    channel_id: C,
-) -> Result<(), Error>
-where
-    G: Into<u64>,
-    C: Into<u64>,
-{
-    let guild_id: u64 = guild_id.into();
-    let (_, handler) = ctx
-        .data()
-        .songbird
-        .join_gateway(guild_id, channel_id.into())
-        .await;
-
-    match handler {
-        Ok(connection_info) => {
-            match ctx
-                .data()
-                .lavalink
-                .create_session_with_songbird(&connection_info)
-                .await
-            {
-                Ok(_) => {
-                    {
-                        let data = ctx.discord().data.read().await;
-                        let mut idle_hash_map =
-                            data.get::<IdleGuildMap>().expect("msg").write().await;
-                        idle_hash_map.insert(guild_id, Instant::now());
-                    }
-
-                    Ok(())
-                }
-                Err(e) => Err(Box::new(e)),
-            }
-        }
-        Err(e) => Err(Box::new(e)),
-    }
-}
-
 fn author_channel_id_from_guild(guild: &Guild, authour_id: &UserId) -> Option<ChannelId> {
     guild
         .voice_states
@@ -72,6 +58,82 @@ fn author_channel_id_from_guild(guild: &Guild, authour_id: &UserId) -> Option<Ch
         .and_then(|voice_state| voice_state.channel_id)
 }
 
+/// Without a DJ role set up everyone can control the music, otherwise only
+/// members with the role or the Manage Server permission can.
+async fn is_dj(ctx: PoiseContext<'_>, guild: &Guild) -> Result<bool, Error> {
+    let settings = {
+        let data = ctx.discord().data.read().await;
+        let db = data.get::<PgPoolContainer>().unwrap();
+        database::Guild::new(db, guild.id).get_settings().await?
+    };
+    let role_id = match settings.dj_role_id {
+        Some(role_id) => RoleId(role_id as u64),
+        None => return Ok(true),
+    };
+
+    let member = guild.member(ctx.discord(), ctx.author().id).await?;
+    Ok(guild.member_permissions(&member).manage_guild() || member.roles.contains(&role_id))
+}
+
+async fn dj_check(ctx: PoiseContext<'_>, guild: &Guild) -> Result<bool, Error> {
+    if is_dj(ctx, guild).await? {
+        return Ok(true);
+    }
+    reply(ctx, "You need the DJ role to use this command.").await?;
+    Ok(false)
+}
+
+/// Counts the author's vote to skip the playing track, and returns whether
+/// enough of the listeners in the bot's voice channel have voted for it.
+async fn vote_skip(ctx: PoiseContext<'_>, guild: &Guild) -> Result<bool, Error> {
+    let listeners = voice_listeners(guild, ctx.discord().cache.current_user_id());
+    if !listeners.contains(&ctx.author().id) {
+        reply(
+            ctx,
+            "You must be listening in the bot's voice channel to vote.",
+        )
+        .await?;
+        return Ok(false);
+    }
+
+    let (vote_skip_percent, skip_votes_map) = {
+        let data = ctx.discord().data.read().await;
+        let db = data.get::<PgPoolContainer>().unwrap();
+        let settings = database::Guild::new(db, guild.id).get_settings().await?;
+        (
+            settings.vote_skip_percent as usize,
+            data.get::<SkipVotesMap>()
+                .expect("Expected SkipVotesMap in TypeMap")
+                .clone(),
+        )
+    };
+    let required = ((listeners.len() * vote_skip_percent + 99) / 100).max(1);
+
+    let votes = {
+        let mut skip_votes_map = skip_votes_map.write().await;
+        let voters = skip_votes_map.entry(guild.id.0).or_default();
+        voters.insert(ctx.author().id.0);
+        // Votes of members that left the channel don't count anymore
+        voters
+            .iter()
+            .filter(|voter| listeners.contains(&UserId(**voter)))
+            .count()
+    };
+    if votes >= required {
+        return Ok(true);
+    }
+
+    reply(
+        ctx,
+        format!(
+            "Voted to skip the current track, {}/{} votes.",
+            votes, required
+        ),
+    )
+    .await?;
+    Ok(false)
+}
+
 /// Have bot join the voice channel you're in.
 #[command(slash_command, aliases("j"))]
 pub async fn join(ctx: PoiseContext<'_>) -> Result<(), Error> {
@@ -85,7 +147,15 @@ pub async fn join(ctx: PoiseContext<'_>) -> Result<(), Error> {
         }
     };
 
-    match join_internal(&ctx, guild.id, channel_id).await {
+    match join_internal(
+        ctx.discord(),
+        &ctx.data().songbird,
+        &ctx.data().lavalink,
+        guild.id,
+        channel_id,
+    )
+    .await
+    {
         Ok(_) => reply(ctx, format!("Joined: {}", channel_id.mention())).await?,
         Err(e) => {
             reply(
@@ -105,6 +175,10 @@ pub async fn join(ctx: PoiseContext<'_>) -> Result<(), Error> {
 pub async fn leave(ctx: PoiseContext<'_>) -> Result<(), Error> {
     let guild = guild_check(ctx).await?;
 
+    if !dj_check(ctx, &guild).await? {
+        return Ok(());
+    }
+
     let manager = &ctx.data().songbird;
 
     if manager.get(guild.id).is_some() {
@@ -112,8 +186,8 @@ pub async fn leave(ctx: PoiseContext<'_>) -> Result<(), Error> {
             reply(ctx, format!("Error leaving voice channel: {}", e)).await?;
         }
 
-        let lava_client = &ctx.data().lavalink;
-        lava_client.destroy(guild.id.0).await?;
+        ctx.data().lavalink.destroy(guild.id.0).await?;
+        clear_player_state(&*ctx.discord().data.read().await, guild.id.0).await;
 
         reply(ctx, "Left the voice channel.").await?;
     } else {
@@ -123,24 +197,227 @@ pub async fn leave(ctx: PoiseContext<'_>) -> Result<(), Error> {
     Ok(())
 }
 
-/// Queue up a song or playlist from YouTube, Twitch, Vimeo, SoundCloud, etc.
-#[command(slash_command, defer_response, aliases("p"))]
-pub async fn play(
+/// Where queries that aren't URLs are searched.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum SearchSource {
+    Youtube,
+    Soundcloud,
+}
+
+impl SearchSource {
+    fn from_flag(soundcloud: bool) -> Self {
+        if soundcloud {
+            SearchSource::Soundcloud
+        } else {
+            SearchSource::Youtube
+        }
+    }
+
+    fn prefix(self) -> &'static str {
+        match self {
+            SearchSource::Youtube => "ytsearch:",
+            SearchSource::Soundcloud => "scsearch:",
+        }
+    }
+}
+
+/// Lets the author pick one of the top search results from a select menu.
+/// Returns `None` if nothing was found or nothing was picked in time.
+async fn pick_track(ctx: PoiseContext<'_>, tracks: &[Track]) -> Result<Option<Track>, Error> {
+    let results = &tracks[..tracks.len().min(SEARCH_RESULT_COUNT)];
+    if results.is_empty() {
+        reply(ctx, "Could not find anything for the search query.").await?;
+        return Ok(None);
+    }
+
+    let mut desc = String::new();
+    for (i, track) in results.iter().enumerate() {
+        let track_info = track.info.as_ref().unwrap();
+        desc.push_str(&format!("`{}.` [", i + 1));
+        push_chopped_str(&mut desc, track_info.title.as_str(), MAX_LIST_ENTRY_LENGTH);
+        desc.push_str(&format!(
+            "]({}) [{}]\n",
+            track_info.uri,
+            if track_info.is_stream {
+                LIVE_INDICATOR.to_owned()
+            } else {
+                display_time_span(track_info.length)
+            }
+        ));
+    }
+
+    let mut message = reply_embed_components(
+        ctx,
+        |e| {
+            e.title("Search Results").description(desc).footer(|f| {
+                f.text(format!(
+                    "Pick a track within {} seconds.",
+                    SEARCH_PICK_TIMEOUT_SECS
+                ))
+            })
+        },
+        |c| {
+            c.create_action_row(|r| {
+                r.create_select_menu(|m| {
+                    m.custom_id("search_pick")
+                        .placeholder("Pick a track")
+                        .options(|o| {
+                            for (i, track) in results.iter().enumerate() {
+                                let track_info = track.info.as_ref().unwrap();
+                                o.create_option(|opt| {
+                                    opt.label(format!(
+                                        "{}. {}",
+                                        i + 1,
+                                        chop_str(track_info.title.as_str(), MAX_LIST_ENTRY_LENGTH)
+                                    ))
+                                    .description(chop_str(
+                                        track_info.author.as_str(),
+                                        MAX_LIST_ENTRY_LENGTH,
+                                    ))
+                                    .value(i)
+                                });
+                            }
+                            o
+                        })
+                })
+            })
+        },
+    )
+    .await?
+    .message()
+    .await?;
+
+    let interaction = message
+        .await_component_interaction(ctx.discord())
+        .author_id(ctx.author().id)
+        .timeout(Duration::from_secs(SEARCH_PICK_TIMEOUT_SECS))
+        .await;
+    let interaction = match interaction {
+        Some(interaction) => interaction,
+        None => {
+            message.edit(ctx.discord(), |m| m.components(|c| c)).await?;
+            reply(ctx, "No track was picked.").await?;
+            return Ok(None);
+        }
+    };
+
+    // Acknowledge the pick and take the menu away so it can't be used twice
+    interaction
+        .create_interaction_response(ctx.discord(), |r| {
+            r.kind(InteractionResponseType::UpdateMessage)
+                .interaction_response_data(|d| d.components(|c| c))
+        })
+        .await?;
+
+    Ok(interaction
+        .data
+        .values
+        .first()
+        .and_then(|value| value.parse::<usize>().ok())
+        .and_then(|i| results.get(i))
+        .cloned())
+}
+
+/// Takes the tracks that go against the guild's music limits out of `tracks`,
+/// and returns them together with the reason why.
+async fn apply_music_limits(
+    ctx: PoiseContext<'_>,
+    guild: &Guild,
+    settings: &GuildSettings,
+    tracks: &mut Vec<Track>,
+) -> Vec<(Track, String)> {
+    let mut rejected = Vec::new();
+    if let Some(max_playlist_size) = settings.max_playlist_size {
+        let max_playlist_size = max_playlist_size as usize;
+        if tracks.len() > max_playlist_size {
+            rejected.extend(
+                tracks
+                    .split_off(max_playlist_size)
+                    .into_iter()
+                    .map(|track| {
+                        (
+                            track,
+                            format!("only {} tracks can be queued at once", max_playlist_size),
+                        )
+                    }),
+            );
+        }
+    }
+
+    let mut queued_by_author = ctx
+        .data()
+        .lavalink
+        .client(guild.id.0)
+        .await
+        .nodes()
+        .await
+        .get(&guild.id.0)
+        .map_or(0, |node| {
+            node.queue
+                .iter()
+                .filter(|queued| {
+                    queued
+                        .requester
+                        .map_or(false, |id| id.0 == ctx.author().id.0)
+                })
+                .count()
+        });
+    let mut allowed = Vec::with_capacity(tracks.len());
+    for track in tracks.drain(..) {
+        let track_info = track.info.as_ref().unwrap();
+        let max_length_ms = settings
+            .max_track_length_secs
+            .map(|max_length| max_length as u64 * 1000);
+        let reason = if track_info.is_stream && !settings.allow_streams {
+            Some("live streams aren't allowed".to_owned())
+        } else if let Some(max_length_ms) =
+            max_length_ms.filter(|max| !track_info.is_stream && track_info.length > *max)
+        {
+            Some(format!("longer than {}", display_time_span(max_length_ms)))
+        } else if let Some(max_tracks) = settings
+            .max_user_tracks
+            .filter(|max| queued_by_author >= *max as usize)
+        {
+            Some(format!(
+                "you can only have {} tracks in the queue",
+                max_tracks
+            ))
+        } else {
+            None
+        };
+        match reason {
+            Some(reason) => rejected.push((track, reason)),
+            None => {
+                queued_by_author += 1;
+                allowed.push(track);
+            }
+        }
+    }
+    *tracks = allowed;
+
+    rejected
+}
+
+async fn play_internal(
     ctx: PoiseContext<'_>,
-    #[rest]
-    #[description = "What to play."]
     query: String,
+    play_next: bool,
+    source: SearchSource,
+    pick: bool,
 ) -> Result<(), Error> {
     let guild = guild_check(ctx).await?;
 
     {
         let data = ctx.discord().data.read().await;
-        let mut last_message_map = data.get::<LastMessageMap>().expect("msg").write().await;
+        let mut last_message_map = data
+            .get::<LastMessageMap>()
+            .expect("Expected LastMessageMap in TypeMap")
+            .write()
+            .await;
         last_message_map.insert(guild.id.0, ctx.channel_id());
     }
 
     let manager = &ctx.data().songbird;
-    let lava_client = &ctx.data().lavalink;
 
     if manager.get(guild.id).is_none() {
         let channel_id = match author_channel_id_from_guild(&guild, &ctx.author().id) {
@@ -155,7 +432,15 @@ pub async fn play(
             }
         };
 
-        if let Err(e) = join_internal(&ctx, guild.id, channel_id).await {
+        if let Err(e) = join_internal(
+            ctx.discord(),
+            &ctx.data().songbird,
+            &ctx.data().lavalink,
+            guild.id,
+            channel_id,
+        )
+        .await
+        {
             reply(
                 ctx,
                 format!("Error joining {}: {}", channel_id.mention(), e),
@@ -165,12 +450,71 @@ pub async fn play(
         }
     }
 
+    // Joining picks the node the player goes on
+    let lava_client = &ctx.data().lavalink.client(guild.id.0).await;
+
+    let settings = {
+        let data = ctx.discord().data.read().await;
+        let db = data.get::<PgPoolContainer>().unwrap();
+        database::Guild::new(db, guild.id).get_settings().await?
+    };
+    // Playlist file entries past what the guild allows aren't worth loading
+    let max_file_tracks = settings
+        .max_playlist_size
+        .map_or(MAX_PLAYLIST_FILE_TRACKS, |max| {
+            (max as usize).min(MAX_PLAYLIST_FILE_TRACKS)
+        });
+
     let mut queueable_tracks = Vec::new();
 
     // Queue up any attachments
     match ctx {
         PoiseContext::Prefix(prefix_ctx) => {
             for attachment in &prefix_ctx.msg.attachments {
+                // Playlist files are queued entry by entry
+                if let Some(format) = PlaylistFormat::from_filename(&attachment.filename) {
+                    if attachment.size > MAX_PLAYLIST_FILE_SIZE {
+                        reply(ctx, "That playlist file is too large.").await?;
+                        return Ok(());
+                    }
+                    let content = attachment.download().await?;
+                    let uris = match import_playlist(format, &String::from_utf8_lossy(&content)) {
+                        Ok(uris) => uris,
+                        Err(_) => {
+                            reply(
+                                ctx,
+                                format!(
+                                    "`{}` is not a valid playlist file.",
+                                    escape_str(&attachment.filename)
+                                ),
+                            )
+                            .await?;
+                            return Ok(());
+                        }
+                    };
+                    // Entries that can't be loaded anymore are left out
+                    for uri in &uris {
+                        if queueable_tracks.len() >= max_file_tracks {
+                            break;
+                        }
+                        let query_result = match Url::parse(uri) {
+                            Ok(url) if matches!(url.scheme(), "http" | "https") => {
+                                lava_client.get_tracks(uri).await
+                            }
+                            // Other schemes could make Lavalink load local files
+                            Ok(_) => continue,
+                            Err(_) => lava_client.auto_search_tracks(uri).await,
+                        };
+                        match query_result {
+                            Ok(query_result) => {
+                                queueable_tracks.extend(query_result.tracks.into_iter().take(1))
+                            }
+                            Err(e) => error!("Failed to load playlist file entry: {}", e),
+                        }
+                    }
+                    continue;
+                }
+
                 // Verify the attachment is playable
                 let playable_content = match &attachment.content_type {
                     Some(t) => t.starts_with("audio") || t.starts_with("video"),
@@ -200,41 +544,80 @@ pub async fn play(
         PoiseContext::Application(_) => {}
     }
 
-    // Load the command query - if playable attachments were also with the message,
-    // the attachments are queued first
-    let query_information = lava_client.auto_search_tracks(&query).await?;
+    let playlist_name = query
+        .trim()
+        .strip_prefix(PLAYLIST_QUERY_PREFIX)
+        .map(str::trim);
+    let is_url = playlist_name.is_none() && Url::parse(query.trim()).is_ok();
+
+    let query_tracks = if let Some(name) = playlist_name {
+        // Saved playlists are looked up in the user's own playlists first, and
+        // then in the ones shared by the server
+        let stored_tracks = {
+            let data = ctx.discord().data.read().await;
+            let db = data.get::<PgPoolContainer>().unwrap();
+            match Playlists::personal(db, ctx.author().id)
+                .get_tracks(name)
+                .await?
+            {
+                Some(tracks) => Some(tracks),
+                None => Playlists::guild(db, guild.id).get_tracks(name).await?,
+            }
+        };
+        match stored_tracks {
+            Some(stored_tracks) => {
+                let playlist_tracks = stored_tracks.len();
+                queueable_tracks.extend(
+                    stored_tracks
+                        .into_iter()
+                        .map(|stored| TrackQueue::from(stored).track),
+                );
+                playlist_tracks
+            }
+            None => {
+                reply(ctx, format!("No playlist named `{}`.", escape_str(name))).await?;
+                return Ok(());
+            }
+        }
+    } else if is_url {
+        // Load the command query - if playable attachments were also with the
+        // message, the attachments are queued first
+        let query_information = lava_client.auto_search_tracks(&query).await?;
 
-    let is_url = Url::parse(query.trim()).is_ok();
+        // If the query was a URL, then it's likely a playlist where all retrieved
+        // tracks are desired
+        let query_tracks = query_information.tracks.len();
+        queueable_tracks.extend(query_information.tracks);
 
-    // If the query was a URL, then it's likely a playlist where all retrieved
-    // tracks are desired - otherwise, only queue the top result
-    let query_tracks = if is_url {
-        query_information.tracks.len()
+        query_tracks
     } else {
+        let query_information = lava_client
+            .get_tracks(format!("{}{}", source.prefix(), query.trim()))
+            .await?;
+
+        // Otherwise, only queue the top result or the one that was picked
+        if pick {
+            match pick_track(ctx, &query_information.tracks).await? {
+                Some(track) => queueable_tracks.push(track),
+                None => return Ok(()),
+            }
+        } else {
+            queueable_tracks.extend(query_information.tracks.into_iter().take(1));
+        }
+
         1
     };
 
-    queueable_tracks.extend_from_slice(
-        &query_information
-            .tracks
-            .iter()
-            .take(query_tracks)
-            .cloned()
-            .collect::<Vec<_>>(),
-    );
-
     if queueable_tracks.is_empty() {
         reply(ctx, "Could not find anything for the search query.").await?;
         return Ok(());
     }
 
-    let queueable_tracks_len = queueable_tracks.len();
-
     // For URLs that point to raw files, Lavalink seems to just return them with a
     // title of "Unknown title" - this is a slightly hacky solution to set the title
     // to the filename of the raw file
     if is_url && query_tracks == 1 {
-        let track_info = &mut queueable_tracks[queueable_tracks_len - 1];
+        let track_info = &mut queueable_tracks[queueable_tracks.len() - 1];
         if track_info.info.is_some() && track_info.info.as_ref().unwrap().title.eq(UNKNOWN_TITLE) {
             track_info.info = match &track_info.info {
                 Some(old_info) => {
@@ -256,6 +639,42 @@ pub async fn play(
         }
     }
 
+    let rejected_tracks = apply_music_limits(ctx, &guild, &settings, &mut queueable_tracks).await;
+    if !rejected_tracks.is_empty() {
+        let rejected_tracks_len = rejected_tracks.len();
+        let mut desc = String::new();
+        for (i, (track, reason)) in rejected_tracks.iter().enumerate() {
+            let track_info = track.info.as_ref().unwrap();
+            desc.push_str("- [");
+            push_chopped_str(&mut desc, track_info.title.as_str(), MAX_LIST_ENTRY_LENGTH);
+            desc.push_str("](");
+            desc.push_str(track_info.uri.as_str());
+            desc.push_str("): ");
+            desc.push_str(reason);
+            if i < rejected_tracks_len - 1 {
+                desc.push('\n');
+                if desc.len() > DESCRIPTION_LENGTH_CUTOFF {
+                    desc.push_str("*…the rest has been clipped*");
+                    break;
+                }
+            }
+        }
+        reply_embed(ctx, |e| {
+            e.title(if rejected_tracks_len != 1 {
+                format!("Skipped {} Tracks:", rejected_tracks_len)
+            } else {
+                format!("Skipped {} Track:", rejected_tracks_len)
+            })
+            .description(desc)
+        })
+        .await?;
+    }
+    if queueable_tracks.is_empty() {
+        return Ok(());
+    }
+
+    let queueable_tracks_len = queueable_tracks.len();
+
     // Queue the tracks up
     for track in &queueable_tracks {
         if let Err(e) = lava_client
@@ -270,13 +689,34 @@ pub async fn play(
         };
     }
 
+    // The tracks were appended to the back, move them up right behind the
+    // playing track
+    if play_next {
+        if let Some(mut node) = lava_client.nodes().await.get_mut(&guild.id.0) {
+            let start = usize::from(node.now_playing.is_some());
+            if node.queue.len() > start + queueable_tracks_len {
+                let mut upcoming = node.queue.split_off(start);
+                let new_tracks = upcoming.split_off(upcoming.len() - queueable_tracks_len);
+                node.queue.extend(new_tracks);
+                node.queue.extend(upcoming);
+            }
+        }
+    } else if settings.fair_queue {
+        interleave_queued_tracks(lava_client, guild.id.0, queueable_tracks_len).await;
+    }
+
     // Notify the user of the added tracks
     if queueable_tracks_len == 1 {
         let track_info = queueable_tracks[0].info.as_ref().unwrap();
         reply(
             ctx,
             format!(
-                "Added to queue: [{}]({}) [{}]",
+                "{}: [{}]({}) [{}]",
+                if play_next {
+                    "Playing next"
+                } else {
+                    "Added to queue"
+                },
                 chop_str(track_info.title.as_str(), MAX_SINGLE_ENTRY_LENGTH),
                 track_info.uri,
                 if track_info.is_stream {
@@ -307,8 +747,12 @@ pub async fn play(
             }
         }
         reply_embed(ctx, |e| {
-            e.title(format!("Added {} Tracks:", queueable_tracks_len))
-                .description(desc)
+            e.title(if play_next {
+                format!("Playing {} Tracks Next:", queueable_tracks_len)
+            } else {
+                format!("Added {} Tracks:", queueable_tracks_len)
+            })
+            .description(desc)
         })
         .await?;
     }
@@ -316,15 +760,151 @@ pub async fn play(
     Ok(())
 }
 
+/// Queue up a song or playlist from YouTube, Twitch, Vimeo, SoundCloud, etc.
+///
+/// Saved playlists are queued with `play playlist:<name>`, and M3U or JSON
+/// playlist files by attaching them to the message. Searches go to
+/// YouTube and queue the top result. Add `pick` to choose from the top
+/// results instead, and `soundcloud` to search SoundCloud.
+///
+/// Usage:
+/// `play never gonna give you up`
+/// `play pick soundcloud lofi beats`
+#[command(slash_command, defer_response, aliases("p"))]
+pub async fn play(
+    ctx: PoiseContext<'_>,
+    #[description = "Pick from the top search results."]
+    #[flag]
+    pick: bool,
+    #[description = "Search SoundCloud instead of YouTube."]
+    #[flag]
+    soundcloud: bool,
+    #[rest]
+    #[description = "What to play."]
+    query: String,
+) -> Result<(), Error> {
+    play_internal(ctx, query, false, SearchSource::from_flag(soundcloud), pick).await
+}
+
+/// Export the queue as a file.
+///
+/// The file is an M3U playlist, or a JSON file when `json` is added. Either
+/// one can be queued again by attaching it to `play`.
+///
+/// Usage:
+/// `export`
+/// `export json`
+#[command(slash_command, rename = "export", aliases("queueexport"))]
+pub async fn queue_export(
+    ctx: PoiseContext<'_>,
+    #[description = "Export as JSON instead of M3U."]
+    #[flag]
+    json: bool,
+) -> Result<(), Error> {
+    let guild = guild_check(ctx).await?;
+
+    let tracks: Vec<StoredTrack> = ctx
+        .data()
+        .lavalink
+        .client(guild.id.0)
+        .await
+        .nodes()
+        .await
+        .get(&guild.id.0)
+        .map(|node| node.queue.iter().map(StoredTrack::from).collect())
+        .unwrap_or_default();
+    if tracks.is_empty() {
+        reply(ctx, "Nothing is in the queue.").await?;
+        return Ok(());
+    }
+
+    let format = PlaylistFormat::from_flag(json);
+    let file = export_playlist(format, &tracks)?;
+    reply_file(
+        ctx,
+        format!("Exported {} tracks.", tracks.len()),
+        format!("queue.{}", format.extension()),
+        file.into_bytes(),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Search for a track and pick which of the results to queue.
+///
+/// Searches YouTube, add `soundcloud` in front of the query to search
+/// SoundCloud instead.
+///
+/// Usage:
+/// `search never gonna give you up`
+/// `search soundcloud lofi beats`
+#[command(slash_command, defer_response, aliases("find"))]
+pub async fn search(
+    ctx: PoiseContext<'_>,
+    #[description = "Search SoundCloud instead of YouTube."]
+    #[flag]
+    soundcloud: bool,
+    #[rest]
+    #[description = "What to search for."]
+    query: String,
+) -> Result<(), Error> {
+    play_internal(ctx, query, false, SearchSource::from_flag(soundcloud), true).await
+}
+
+/// Queue up a song or playlist to be played right after the current track.
+#[command(slash_command, defer_response, rename = "playnext", aliases("pn"))]
+pub async fn play_next(
+    ctx: PoiseContext<'_>,
+    #[rest]
+    #[description = "What to play next."]
+    query: String,
+) -> Result<(), Error> {
+    play_internal(ctx, query, true, SearchSource::Youtube, false).await
+}
+
 /// Skip the current track.
+///
+/// If the server has a DJ role, members without it have to vote to skip
+/// tracks they didn't request themselves. The track is skipped once enough of
+/// the listeners voted for it.
 #[command(slash_command, aliases("next", "stop", "n", "s"))]
 pub async fn skip(ctx: PoiseContext<'_>) -> Result<(), Error> {
     let guild = guild_check(ctx).await?;
 
-    let lava_client = &ctx.data().lavalink;
+    let lava_client = &ctx.data().lavalink.client(guild.id.0).await;
+
+    let requester = lava_client.nodes().await.get(&guild.id.0).and_then(|node| {
+        node.now_playing
+            .as_ref()
+            .map(|now_playing| now_playing.requester)
+    });
+    let is_requester = match requester {
+        Some(requester) => requester.map_or(false, |requester| requester.0 == ctx.author().id.0),
+        None => {
+            reply(ctx, "Nothing to skip.").await?;
+            return Ok(());
+        }
+    };
+    if !is_requester && !is_dj(ctx, &guild).await? && !vote_skip(ctx, &guild).await? {
+        return Ok(());
+    }
 
     if let Some(track) = lava_client.skip(guild.id.0).await {
         let track_info = track.track.info.as_ref().unwrap();
+        // When looping the queue, skipped tracks go around again as well
+        let loop_mode = {
+            let data = ctx.discord().data.read().await;
+            let loop_mode_map = data
+                .get::<LoopModeMap>()
+                .expect("Expected LoopModeMap in TypeMap")
+                .read()
+                .await;
+            loop_mode_map.get(&guild.id.0).copied().unwrap_or_default()
+        };
+        if loop_mode == LoopMode::Queue {
+            enqueue(lava_client, guild.id.0, track.clone()).await?;
+        }
         // If the queue is now empty, the player needs to be stopped
         if lava_client
             .nodes()
@@ -362,7 +942,7 @@ pub async fn skip(ctx: PoiseContext<'_>) -> Result<(), Error> {
 pub async fn pause(ctx: PoiseContext<'_>) -> Result<(), Error> {
     let guild = guild_check(ctx).await?;
 
-    let lava_client = &ctx.data().lavalink;
+    let lava_client = &ctx.data().lavalink.client(guild.id.0).await;
 
     if let Err(e) = lava_client.pause(guild.id.0).await {
         reply(ctx, "Failed to pause playback.").await?;
@@ -370,6 +950,16 @@ pub async fn pause(ctx: PoiseContext<'_>) -> Result<(), Error> {
         return Ok(());
     };
 
+    {
+        let data = ctx.discord().data.read().await;
+        let mut idle_hash_map = data
+            .get::<IdleGuildMap>()
+            .expect("Expected IdleGuildMap in TypeMap")
+            .write()
+            .await;
+        idle_hash_map.insert(guild.id.0, Instant::now());
+    }
+
     reply(ctx, "Paused playback.").await?;
 
     Ok(())
@@ -382,7 +972,7 @@ pub async fn pause(ctx: PoiseContext<'_>) -> Result<(), Error> {
 pub async fn resume(ctx: PoiseContext<'_>) -> Result<(), Error> {
     let guild = guild_check(ctx).await?;
 
-    let lava_client = &ctx.data().lavalink;
+    let lava_client = &ctx.data().lavalink.client(guild.id.0).await;
 
     if let Err(e) = lava_client.resume(guild.id.0).await {
         reply(ctx, "Failed to resume playback.").await?;
@@ -390,122 +980,171 @@ pub async fn resume(ctx: PoiseContext<'_>) -> Result<(), Error> {
         return Ok(());
     };
 
+    {
+        let data = ctx.discord().data.read().await;
+        let mut idle_hash_map = data
+            .get::<IdleGuildMap>()
+            .expect("Expected IdleGuildMap in TypeMap")
+            .write()
+            .await;
+        idle_hash_map.remove(&guild.id.0);
+        let mut alone_paused_guilds = data
+            .get::<AlonePausedGuilds>()
+            .expect("Expected AlonePausedGuilds in TypeMap")
+            .write()
+            .await;
+        alone_paused_guilds.remove(&guild.id.0);
+    }
+
     reply(ctx, "Resumed playback.").await?;
 
     Ok(())
 }
 
-/// Seek to a specific time in the current track.
-///
-/// You can specify the time to skip to as a timecode (`2:35`) or as individual
-/// time values (`2m35s`).
-///
-/// If the time specified is past the end of the track, the track ends.
-#[command(slash_command, aliases("scrub", "jump"))]
-pub async fn seek(
-    ctx: PoiseContext<'_>,
-    #[rest]
-    #[description = "What time to skip to."]
-    time: String,
-) -> Result<(), Error> {
-    // Constants
-    const COLON: char = ':';
-    const DECIMAL: char = '.';
-
-    // Parse the time - this is a little hacky and gross, but it allows for support
-    // of timecodes like `2:35`. This is more ergonomic for users than something
-    // like `2m35s`, and this way both formats are supported.
-    let mut invalid_value = false;
-    let mut time_prepared = String::with_capacity(time.len());
-    'prepare_time: for timecode in time.split_whitespace() {
-        // First iteration to find indices and make sure the timecode is valid
-        let mut colon_index_first = None;
-        let mut colon_index_second = None;
-        let mut decimal_index = None;
-        for (i, c) in timecode.chars().enumerate() {
-            if c == COLON {
-                if colon_index_first.is_none() {
-                    colon_index_first = Some(i);
-                } else if colon_index_second.is_none() {
-                    colon_index_second = Some(i);
-                } else {
-                    // Maximum of two colons in a timecode
-                    invalid_value = true;
-                    break 'prepare_time;
-                }
-                if decimal_index.is_some() {
-                    // Colons don't come after decimals
-                    invalid_value = true;
-                    break 'prepare_time;
-                }
-            } else if c == DECIMAL {
-                if decimal_index.is_none() {
-                    decimal_index = Some(i);
-                } else {
-                    // Only one decimal value
-                    invalid_value = true;
-                    break 'prepare_time;
-                }
-            }
-        }
-
-        // Second iteration using those indices to convert the timecode to a duration
-        // representation
-        let mut new_word = String::with_capacity(timecode.len());
-        for (i, c) in timecode.chars().enumerate() {
-            if colon_index_first.is_some() && i == colon_index_first.unwrap() {
-                if colon_index_second.is_some() {
-                    new_word.push('h');
-                } else {
-                    new_word.push('m');
-                }
-            } else if colon_index_second.is_some() && i == colon_index_second.unwrap() {
-                new_word.push('m');
-            } else if decimal_index.is_some() && i == decimal_index.unwrap() {
-                new_word.push('s');
-            } else {
-                new_word.push(c);
-            }
-        }
-        if decimal_index.is_some() {
-            new_word.push_str("ms");
-        } else if colon_index_first.is_some() {
-            new_word.push('s');
-        }
+/// Moves the playing track to the given time, making sure the time is within
+/// the track.
+async fn seek_internal(ctx: PoiseContext<'_>, seek_time: SeekTime) -> Result<(), Error> {
+    let guild = guild_check(ctx).await?;
 
-        // Push the prepared timecode to the result
-        time_prepared.push_str(new_word.as_str());
-        time_prepared.push(' ');
-    }
-    if invalid_value {
-        reply(ctx, "Invalid value for time.").await?;
+    if !dj_check(ctx, &guild).await? {
         return Ok(());
     }
 
-    let time_dur = match parse_duration(time_prepared.as_str()) {
-        Ok(duration) => duration,
-        Err(_) => {
-            reply(ctx, "Invalid value for time.").await?;
-            return Ok(());
-        }
-    };
+    let lava_client = &ctx.data().lavalink.client(guild.id.0).await;
 
-    // Seek to the parsed time
-    let guild = guild_check(ctx).await?;
+    let playing = lava_client
+        .nodes()
+        .await
+        .get(&guild.id.0)
+        .and_then(|node| Some((node.now_playing.clone()?, node.is_paused)));
+    let (now_playing, is_paused) = match playing {
+        Some(playing) => playing,
+        None => {
+            reply(ctx, "Nothing is playing at the moment.").await?;
+            return Ok(());
+        }
+    };
+    let track_info = now_playing.track.info.as_ref().unwrap();
+    if track_info.is_stream {
+        reply(ctx, "Live streams can't be seeked.").await?;
+        return Ok(());
+    }
 
-    let lava_client = &ctx.data().lavalink;
+    let player_position_map = {
+        let data = ctx.discord().data.read().await;
+        data.get::<PlayerPositionMap>()
+            .expect("Expected PlayerPositionMap in TypeMap")
+            .clone()
+    };
+    let position = estimate_position(player_position_map.read().await.get(&guild.id.0), is_paused)
+        .min(track_info.length);
+    let target = match seek_time {
+        SeekTime::Absolute(time) => time.as_millis() as u64,
+        SeekTime::Forward(offset) => position + offset.as_millis() as u64,
+        SeekTime::Backward(offset) => position.saturating_sub(offset.as_millis() as u64),
+    };
+    if target >= track_info.length {
+        reply(
+            ctx,
+            format!(
+                "The track is only {} long.",
+                display_time_span(track_info.length)
+            ),
+        )
+        .await?;
+        return Ok(());
+    }
 
-    if let Err(e) = lava_client.seek(guild.id.0, time_dur).await {
+    if let Err(e) = lava_client
+        .seek(guild.id.0, Duration::from_millis(target))
+        .await
+    {
         reply(ctx, "Failed to seek to the specified time.").await?;
         eprintln!("Failed to seek to the specified time: {}", e);
         return Ok(());
     };
-
-    reply(ctx, "Scrubbed to the specified time.").await?;
+    // Lavalink only reports the new position with its next player update
+    player_position_map
+        .write()
+        .await
+        .insert(guild.id.0, (target, Instant::now()));
+
+    reply(
+        ctx,
+        format!(
+            "Scrubbed to {} / {}.",
+            display_time_span(target),
+            display_time_span(track_info.length)
+        ),
+    )
+    .await?;
 
     Ok(())
 }
 
+/// Seek to a specific time in the current track.
+///
+/// You can specify the time to skip to as a timecode (`2:35`) or as individual
+/// time values (`2m35s`). Start the time with `+` or `-` to seek relative to
+/// the current position (`+30s`, `-1:00`).
+#[command(slash_command, aliases("scrub", "jump"))]
+pub async fn seek(
+    ctx: PoiseContext<'_>,
+    #[rest]
+    #[description = "What time to skip to."]
+    time: String,
+) -> Result<(), Error> {
+    match parse_seek_time(time.as_str()) {
+        Some(seek_time) => seek_internal(ctx, seek_time).await,
+        None => {
+            reply(ctx, "Invalid value for time.").await?;
+            Ok(())
+        }
+    }
+}
+
+/// Fast-forward the current track, by 10 seconds unless told otherwise.
+#[command(slash_command, aliases("ff", "fastforward"))]
+pub async fn forward(
+    ctx: PoiseContext<'_>,
+    #[rest]
+    #[description = "How far to skip ahead."]
+    time: Option<String>,
+) -> Result<(), Error> {
+    let offset = match time {
+        Some(time) => parse_time(time.as_str()),
+        None => Some(Duration::from_secs(SEEK_STEP_SECS)),
+    };
+    match offset {
+        Some(offset) => seek_internal(ctx, SeekTime::Forward(offset)).await,
+        None => {
+            reply(ctx, "Invalid value for time.").await?;
+            Ok(())
+        }
+    }
+}
+
+/// Rewind the current track, by 10 seconds unless told otherwise.
+#[command(slash_command, aliases("rw"))]
+pub async fn rewind(
+    ctx: PoiseContext<'_>,
+    #[rest]
+    #[description = "How far to go back."]
+    time: Option<String>,
+) -> Result<(), Error> {
+    let offset = match time {
+        Some(time) => parse_time(time.as_str()),
+        None => Some(Duration::from_secs(SEEK_STEP_SECS)),
+    };
+    match offset {
+        Some(offset) => seek_internal(ctx, SeekTime::Backward(offset)).await,
+        None => {
+            reply(ctx, "Invalid value for time.").await?;
+            Ok(())
+        }
+    }
+}
+
 /// Clear the playback queue.
 ///
 /// In addition to clearing the queue, this also resets the queue position for
@@ -515,7 +1154,11 @@ pub async fn seek(
 pub async fn clear(ctx: PoiseContext<'_>) -> Result<(), Error> {
     let guild = guild_check(ctx).await?;
 
-    let lava_client = &ctx.data().lavalink;
+    if !dj_check(ctx, &guild).await? {
+        return Ok(());
+    }
+
+    let lava_client = &ctx.data().lavalink.client(guild.id.0).await;
 
     while lava_client.skip(guild.id.0).await.is_some() {}
     lava_client
@@ -540,92 +1183,1569 @@ pub async fn clear(ctx: PoiseContext<'_>) -> Result<(), Error> {
 pub async fn now_playing(ctx: PoiseContext<'_>) -> Result<(), Error> {
     let guild = guild_check(ctx).await?;
 
-    let lava_client = &ctx.data().lavalink;
+    let lava_client = &ctx.data().lavalink.client(guild.id.0).await;
 
-    let mut something_playing = false;
-    if let Some(node) = lava_client.nodes().await.get(&guild.id.0) {
-        if let Some(now_playing) = &node.now_playing {
-            let track_info = now_playing.track.info.as_ref().unwrap();
-            reply_embed(ctx, |e| {
-                e.title("Now Playing")
-                    .field(
-                        "Track:",
-                        format!(
-                            "[{}]({})",
-                            chop_str(track_info.title.as_str(), MAX_SINGLE_ENTRY_LENGTH),
-                            track_info.uri,
-                        ),
-                        false,
-                    )
-                    .field("Duration:", display_time_span(track_info.length), true)
-                    .field(
-                        "Requested By:",
-                        UserId(
-                            now_playing
-                                .requester
-                                .expect("Expected a requester associated with a playing track")
-                                .0,
-                        )
-                        .mention(),
-                        true,
-                    )
-            })
-            .await?;
-            something_playing = true;
+    let filters = {
+        let data = ctx.discord().data.read().await;
+        let filters_map = data
+            .get::<FiltersMap>()
+            .expect("Expected FiltersMap in TypeMap")
+            .read()
+            .await;
+        filters_map
+            .get(&guild.id.0)
+            .and_then(|filters| filters.describe())
+    };
+
+    let playing = lava_client
+        .nodes()
+        .await
+        .get(&guild.id.0)
+        .and_then(|node| Some((node.now_playing.clone()?, node.is_paused)));
+    let (now_playing, is_paused) = match playing {
+        Some(playing) => playing,
+        None => {
+            reply(ctx, "Nothing is playing at the moment.").await?;
+            return Ok(());
         }
-    }
-    if !something_playing {
-        reply(ctx, "Nothing is playing at the moment.").await?;
+    };
+
+    let (position, now_playing_message_map) = {
+        let data = ctx.discord().data.read().await;
+        let player_position_map = data
+            .get::<PlayerPositionMap>()
+            .expect("Expected PlayerPositionMap in TypeMap")
+            .read()
+            .await;
+        (
+            estimate_position(player_position_map.get(&guild.id.0), is_paused),
+            data.get::<NowPlayingMessageMap>()
+                .expect("Expected NowPlayingMessageMap in TypeMap")
+                .clone(),
+        )
+    };
+
+    let message = reply_embed(ctx, |e| {
+        now_playing_embed(e, &now_playing, position, filters.as_deref())
+    })
+    .await?
+    .message()
+    .await?;
+
+    // Only the latest now playing message is kept up to date, the previous
+    // one is removed so the channel doesn't fill up with stale progress bars.
+    let previous = now_playing_message_map.write().await.insert(
+        guild.id.0,
+        NowPlayingMessage {
+            channel_id: message.channel_id,
+            message_id: message.id,
+            last_edit: Instant::now(),
+        },
+    );
+    if let Some(previous) = previous {
+        let _ = previous
+            .channel_id
+            .delete_message(ctx.discord(), previous.message_id)
+            .await;
     }
 
     Ok(())
 }
 
+/// Works out how long it takes until each queued track plays, given how far
+/// into the playing track the player is. Tracks queued after a live stream
+/// have no start time. The last entry is the total remaining time.
+fn queue_start_times(queue: &[TrackQueue], position: Option<u64>) -> Vec<Option<u64>> {
+    let mut start_times = Vec::with_capacity(queue.len() + 1);
+    let mut elapsed = Some(0);
+    for (i, queued_track) in queue.iter().enumerate() {
+        start_times.push(elapsed);
+        let track_info = queued_track.track.info.as_ref().unwrap();
+        elapsed = elapsed.filter(|_| !track_info.is_stream).map(|elapsed| {
+            elapsed
+                + match position {
+                    Some(position) if i == 0 => track_info.length.saturating_sub(position),
+                    _ => track_info.length,
+                }
+        });
+    }
+    start_times.push(elapsed);
+    start_times
+}
+
+fn queue_embed<'a>(
+    e: &'a mut CreateEmbed,
+    queue: &[TrackQueue],
+    start_times: &[Option<u64>],
+    is_playing: bool,
+    page: usize,
+    fair_queue: bool,
+) -> &'a mut CreateEmbed {
+    let page_count = (queue.len() + QUEUE_PAGE_SIZE - 1) / QUEUE_PAGE_SIZE;
+    let mut desc = String::new();
+    for (i, queued_track) in queue
+        .iter()
+        .enumerate()
+        .skip(page * QUEUE_PAGE_SIZE)
+        .take(QUEUE_PAGE_SIZE)
+    {
+        let track_info = queued_track.track.info.as_ref().unwrap();
+        desc.push_str(format!("`{}.` [", i + 1).as_str());
+        push_chopped_str(&mut desc, track_info.title.as_str(), MAX_LIST_ENTRY_LENGTH);
+        desc.push_str("](");
+        desc.push_str(track_info.uri.as_str());
+        desc.push_str(") [");
+        if track_info.is_stream {
+            desc.push_str(LIVE_INDICATOR);
+        } else {
+            desc.push_str(display_time_span(track_info.length).as_str());
+        }
+        desc.push(']');
+        if let Some(requester) = queued_track.requester {
+            desc.push_str(" - ");
+            desc.push_str(UserId(requester.0).mention().to_string().as_str());
+        }
+        desc.push_str(" - ");
+        match start_times[i] {
+            _ if i == 0 && is_playing => desc.push_str("*playing*"),
+            Some(start_time) => {
+                desc.push_str("in ");
+                desc.push_str(display_time_span(start_time).as_str());
+            }
+            None => desc.push_str("after a live stream"),
+        }
+        desc.push('\n');
+    }
+
+    let mut footer = format!("Page {}/{}", page + 1, page_count);
+    match start_times[queue.len()] {
+        Some(remaining) => {
+            footer.push_str(" - ");
+            footer.push_str(display_time_span(remaining).as_str());
+            footer.push_str(" remaining");
+        }
+        None => footer.push_str(" - Live stream in the queue"),
+    }
+    if fair_queue {
+        footer.push_str(" - Fair queue is on");
+    }
+
+    e.colour(EMBED_COLOUR)
+        .title(if queue.len() != 1 {
+            format!("Queue ({} total tracks):", queue.len())
+        } else {
+            format!("Queue ({} total track):", queue.len())
+        })
+        .description(desc)
+        .footer(|f| f.text(footer))
+}
+
+fn queue_buttons(
+    c: &mut CreateComponents,
+    page: usize,
+    page_count: usize,
+) -> &mut CreateComponents {
+    c.create_action_row(|r| {
+        r.create_button(|b| {
+            b.custom_id("queue_previous")
+                .label("Previous")
+                .style(ButtonStyle::Secondary)
+                .disabled(page == 0)
+        })
+        .create_button(|b| {
+            b.custom_id("queue_next")
+                .label("Next")
+                .style(ButtonStyle::Secondary)
+                .disabled(page + 1 >= page_count)
+        })
+    })
+}
+
 /// Show the playback queue.
+///
+/// Long queues are split into pages, which can be flipped through with the
+/// buttons below the queue.
 #[command(slash_command, aliases("q"))]
 pub async fn queue(ctx: PoiseContext<'_>) -> Result<(), Error> {
     let guild = guild_check(ctx).await?;
 
-    let lava_client = &ctx.data().lavalink;
-
-    let mut something_in_queue = false;
-    if let Some(node) = lava_client.nodes().await.get(&guild.id.0) {
-        let queue = &node.queue;
-        let queue_len = queue.len();
-
-        if queue_len > 0 {
-            something_in_queue = true;
-
-            let mut desc = String::new();
-            for (i, queued_track) in queue.iter().enumerate() {
-                let track_info = queued_track.track.info.as_ref().unwrap();
-                desc.push_str(format!("`{}.` [", i + 1).as_str());
-                push_chopped_str(&mut desc, track_info.title.as_str(), MAX_LIST_ENTRY_LENGTH);
-                desc.push_str("](");
-                desc.push_str(track_info.uri.as_str());
-                desc.push(')');
-                if i < queue_len - 1 {
-                    desc.push('\n');
-                    if desc.len() > DESCRIPTION_LENGTH_CUTOFF {
-                        desc.push_str("*…the rest has been clipped*");
-                        break;
-                    }
-                }
+    let fair_queue = {
+        let data = ctx.discord().data.read().await;
+        let db = data.get::<PgPoolContainer>().unwrap();
+        database::Guild::new(db, guild.id)
+            .get_settings()
+            .await?
+            .fair_queue
+    };
+
+    let lava_client = &ctx.data().lavalink.client(guild.id.0).await;
+
+    let queued = lava_client.nodes().await.get(&guild.id.0).map(|node| {
+        (
+            node.queue.clone(),
+            node.now_playing.is_some(),
+            node.is_paused,
+        )
+    });
+    let (queue, is_playing, is_paused) = match queued {
+        Some(queued) if !queued.0.is_empty() => queued,
+        _ => {
+            reply(ctx, "Nothing is in the queue.").await?;
+            return Ok(());
+        }
+    };
+
+    let position = if is_playing {
+        let data = ctx.discord().data.read().await;
+        let player_position_map = data
+            .get::<PlayerPositionMap>()
+            .expect("Expected PlayerPositionMap in TypeMap")
+            .read()
+            .await;
+        Some(estimate_position(
+            player_position_map.get(&guild.id.0),
+            is_paused,
+        ))
+    } else {
+        None
+    };
+    let start_times = queue_start_times(&queue, position);
+
+    let page_count = (queue.len() + QUEUE_PAGE_SIZE - 1) / QUEUE_PAGE_SIZE;
+    let mut page = 0;
+    let mut message = reply_embed_components(
+        ctx,
+        |e| queue_embed(e, &queue, &start_times, is_playing, page, fair_queue),
+        |c| {
+            if page_count > 1 {
+                queue_buttons(c, page, page_count);
             }
-            reply_embed(ctx, |e| {
-                e.title(if queue_len != 1 {
-                    format!("Queue ({} total tracks):", queue_len)
-                } else {
-                    format!("Queue ({} total track):", queue_len)
-                })
-                .description(desc)
+            c
+        },
+    )
+    .await?
+    .message()
+    .await?;
+    if page_count <= 1 {
+        return Ok(());
+    }
+
+    while let Some(interaction) = message
+        .await_component_interaction(ctx.discord())
+        .timeout(Duration::from_secs(QUEUE_PAGE_TIMEOUT_SECS))
+        .await
+    {
+        match interaction.data.custom_id.as_str() {
+            "queue_previous" => page = page.saturating_sub(1),
+            "queue_next" => page = (page + 1).min(page_count - 1),
+            _ => continue,
+        }
+        interaction
+            .create_interaction_response(ctx.discord(), |r| {
+                r.kind(InteractionResponseType::UpdateMessage)
+                    .interaction_response_data(|d| {
+                        d.create_embed(|e| {
+                            queue_embed(e, &queue, &start_times, is_playing, page, fair_queue)
+                        })
+                        .components(|c| queue_buttons(c, page, page_count))
+                    })
             })
             .await?;
+    }
+
+    // The buttons stop working once the collector is gone
+    message.edit(ctx.discord(), |m| m.components(|c| c)).await?;
+
+    Ok(())
+}
+
+/// Show the recently played tracks, newest first.
+#[command(slash_command, aliases("recent"))]
+pub async fn history(ctx: PoiseContext<'_>) -> Result<(), Error> {
+    let guild = guild_check(ctx).await?;
+
+    let history = {
+        let data = ctx.discord().data.read().await;
+        let history_map = data
+            .get::<HistoryMap>()
+            .expect("Expected HistoryMap in TypeMap");
+        load_history(
+            history_map,
+            data.get::<PgPoolContainer>().unwrap(),
+            guild.id.0,
+        )
+        .await?;
+        history_map
+            .read()
+            .await
+            .get(&guild.id.0)
+            .cloned()
+            .unwrap_or_default()
+    };
+    if history.is_empty() {
+        reply(ctx, "Nothing was played yet.").await?;
+        return Ok(());
+    }
+
+    let mut desc = String::new();
+    for (i, played_track) in history.iter().enumerate() {
+        let track_info = played_track.track.info.as_ref().unwrap();
+        desc.push_str(format!("`{}.` [", i + 1).as_str());
+        push_chopped_str(&mut desc, track_info.title.as_str(), MAX_LIST_ENTRY_LENGTH);
+        desc.push_str("](");
+        desc.push_str(track_info.uri.as_str());
+        desc.push(')');
+        if let Some(requester) = played_track.requester {
+            desc.push_str(" - ");
+            desc.push_str(UserId(requester.0).mention().to_string().as_str());
+        }
+        if i < history.len() - 1 {
+            desc.push('\n');
+            if desc.len() > DESCRIPTION_LENGTH_CUTOFF {
+                desc.push_str("*…the rest has been clipped*");
+                break;
+            }
         }
     }
-    if !something_in_queue {
-        reply(ctx, "Nothing is in the queue.").await?;
+    reply_embed(ctx, |e| e.title("Recently Played").description(desc)).await?;
+
+    Ok(())
+}
+
+/// Queue the previously played track again, right after the current one.
+#[command(slash_command, rename = "previous", aliases("prev", "replay", "back"))]
+pub async fn previous(ctx: PoiseContext<'_>) -> Result<(), Error> {
+    let guild = guild_check(ctx).await?;
+
+    if ctx.data().songbird.get(guild.id).is_none() {
+        reply(ctx, "Not in a voice channel.").await?;
+        return Ok(());
+    }
+
+    let lava_client = &ctx.data().lavalink.client(guild.id.0).await;
+
+    let is_playing = lava_client
+        .nodes()
+        .await
+        .get(&guild.id.0)
+        .map_or(false, |node| node.now_playing.is_some());
+    let track = {
+        let data = ctx.discord().data.read().await;
+        let history_map = data
+            .get::<HistoryMap>()
+            .expect("Expected HistoryMap in TypeMap");
+        load_history(
+            history_map,
+            data.get::<PgPoolContainer>().unwrap(),
+            guild.id.0,
+        )
+        .await?;
+        // While a track plays, it's the newest entry of the history itself
+        history_map
+            .read()
+            .await
+            .get(&guild.id.0)
+            .and_then(|history| history.get(usize::from(is_playing)).cloned())
+    };
+    let track = match track {
+        Some(track) => TrackQueue {
+            start_time: 0,
+            end_time: None,
+            requester: Some(LavalinkUserId(ctx.author().id.0)),
+            ..track
+        },
+        None => {
+            reply(ctx, "Nothing was played before.").await?;
+            return Ok(());
+        }
+    };
+
+    let track_info = track.track.info.clone().unwrap();
+    enqueue_front(lava_client, guild.id.0, track).await?;
+    reply(
+        ctx,
+        format!(
+            "Playing next: [{}]({})",
+            chop_str(track_info.title.as_str(), MAX_SINGLE_ENTRY_LENGTH),
+            track_info.uri
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+fn display_hours(millis: i64) -> String {
+    format!("{:.1} hours", millis as f64 / 3_600_000.0)
+}
+
+/// Show what the server listened to the most.
+///
+/// Mention someone to see what they queued up instead, and add `year` to
+/// only count this year's plays.
+///
+/// Usage:
+/// `musicstats`
+/// `musicstats year @user`
+#[command(slash_command, rename = "musicstats", aliases("wrapped"))]
+pub async fn music_stats(
+    ctx: PoiseContext<'_>,
+    #[description = "Only count this year's plays."]
+    #[flag]
+    year: bool,
+    #[description = "Whose stats to show."] user: Option<User>,
+) -> Result<(), Error> {
+    let guild = guild_check(ctx).await?;
+
+    let since = if year {
+        Utc.ymd(Utc::now().year(), 1, 1).and_hms(0, 0, 0)
+    } else {
+        Utc.timestamp(0, 0)
+    };
+    let requester = user.as_ref().map(|user| user.id.0 as i64);
+
+    let (totals, top_tracks, top_listeners) = {
+        let data = ctx.discord().data.read().await;
+        let db = data.get::<PgPoolContainer>().unwrap();
+        let db_guild = database::Guild::new(db, guild.id);
+        let totals = db_guild.get_music_totals(since, requester).await?;
+        let top_tracks = db_guild
+            .get_top_tracks(since, requester, MUSIC_STATS_LENGTH)
+            .await?;
+        // The listeners only make sense for the whole server
+        let top_listeners = if requester.is_none() {
+            db_guild
+                .get_top_listeners(since, MUSIC_STATS_LENGTH)
+                .await?
+        } else {
+            Vec::new()
+        };
+        (totals, top_tracks, top_listeners)
+    };
+    if totals.plays == 0 {
+        reply(ctx, "Nothing was played yet.").await?;
+        return Ok(());
+    }
+
+    let mut tracks_desc = String::new();
+    for (i, track) in top_tracks.iter().enumerate() {
+        tracks_desc.push_str(format!("`{}.` [", i + 1).as_str());
+        push_chopped_str(
+            &mut tracks_desc,
+            track.title.as_str(),
+            MAX_LIST_ENTRY_LENGTH,
+        );
+        tracks_desc.push_str(
+            format!(
+                "]({}) - {} play{}\n",
+                track.uri,
+                track.plays,
+                if track.plays != 1 { "s" } else { "" }
+            )
+            .as_str(),
+        );
+    }
+    let mut listeners_desc = String::new();
+    for (i, listener) in top_listeners.iter().enumerate() {
+        listeners_desc.push_str(
+            format!(
+                "`{}.` {} - {}, {} play{}\n",
+                i + 1,
+                UserId(listener.requester as u64).mention(),
+                display_hours(listener.played_ms),
+                listener.plays,
+                if listener.plays != 1 { "s" } else { "" }
+            )
+            .as_str(),
+        );
+    }
+
+    reply_embed(ctx, |e| {
+        e.title(match &user {
+            Some(user) => format!("Music Stats of {}", user.name),
+            None => "Music Stats".to_owned(),
+        })
+        .description(format!(
+            "**{}** listened over **{}** play{}{}.",
+            display_hours(totals.played_ms),
+            totals.plays,
+            if totals.plays != 1 { "s" } else { "" },
+            if year { " this year" } else { "" }
+        ))
+        .field("Top Tracks:", tracks_desc, false);
+        if !listeners_desc.is_empty() {
+            e.field("Top Listeners:", listeners_desc, false);
+        }
+        e
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Loop the current track or the whole queue.
+///
+/// The available modes are `off`, `track` and `queue`. In `track` mode the
+/// current track is replayed once it ends, in `queue` mode finished and skipped
+/// tracks are sent to the back of the queue.
+///
+/// Run without a mode to see the current one.
+#[command(slash_command, rename = "loop", aliases("repeat"))]
+pub async fn loop_mode(
+    ctx: PoiseContext<'_>,
+    #[description = "Either off, track or queue."] mode: Option<String>,
+) -> Result<(), Error> {
+    let guild = guild_check(ctx).await?;
+
+    let data = ctx.discord().data.read().await;
+    let loop_mode_map = data
+        .get::<LoopModeMap>()
+        .expect("Expected LoopModeMap in TypeMap");
+
+    let mode = match mode {
+        Some(mode) => match LoopMode::from_str(mode.trim()) {
+            Ok(mode) => mode,
+            Err(_) => {
+                reply(
+                    ctx,
+                    "Invalid loop mode, use one of `off`, `track` or `queue`.",
+                )
+                .await?;
+                return Ok(());
+            }
+        },
+        None => {
+            let mode = loop_mode_map
+                .read()
+                .await
+                .get(&guild.id.0)
+                .copied()
+                .unwrap_or_default();
+            reply(ctx, format!("Loop mode is `{}`.", mode.to_string())).await?;
+            return Ok(());
+        }
+    };
+
+    loop_mode_map.write().await.insert(guild.id.0, mode);
+
+    reply(
+        ctx,
+        match mode {
+            LoopMode::Off => "Looping is now disabled.",
+            LoopMode::Track => "Looping the current track.",
+            LoopMode::Queue => "Looping the queue.",
+        },
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Shuffle the upcoming tracks in the queue.
+#[command(slash_command, aliases("shuf"))]
+pub async fn shuffle(ctx: PoiseContext<'_>) -> Result<(), Error> {
+    let guild = guild_check(ctx).await?;
+
+    if !dj_check(ctx, &guild).await? {
+        return Ok(());
+    }
+
+    let lava_client = &ctx.data().lavalink.client(guild.id.0).await;
+
+    let shuffled = match lava_client.nodes().await.get_mut(&guild.id.0) {
+        Some(mut node) => {
+            let start = usize::from(node.now_playing.is_some());
+            if node.queue.len() > start + 1 {
+                node.queue[start..].shuffle(&mut rand::thread_rng());
+                true
+            } else {
+                false
+            }
+        }
+        None => false,
+    };
+
+    if shuffled {
+        reply(ctx, "Shuffled the queue.").await?;
+    } else {
+        reply(ctx, "Not enough tracks in the queue to shuffle.").await?;
     }
 
     Ok(())
 }
+
+/// Remove tracks from the queue.
+///
+/// Takes a single position (`3`) or a range of positions (`3-7`) as shown by
+/// `queue`. The track that is currently playing can't be removed, skip it
+/// instead.
+#[command(slash_command, aliases("rm"))]
+pub async fn remove(
+    ctx: PoiseContext<'_>,
+    #[rest]
+    #[description = "Position or range of positions to remove."]
+    positions: String,
+) -> Result<(), Error> {
+    let guild = guild_check(ctx).await?;
+
+    if !dj_check(ctx, &guild).await? {
+        return Ok(());
+    }
+
+    let (from, to) = match parse_range(&positions) {
+        Some(range) => range,
+        None => {
+            reply(
+                ctx,
+                "Invalid position, use a number like `3` or a range like `3-7`.",
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+
+    let lava_client = &ctx.data().lavalink.client(guild.id.0).await;
+
+    let removed = match lava_client.nodes().await.get_mut(&guild.id.0) {
+        Some(mut node) => {
+            let start = usize::from(node.now_playing.is_some());
+            if from > start && to <= node.queue.len() {
+                Some(node.queue.drain(from - 1..to).collect::<Vec<_>>())
+            } else {
+                None
+            }
+        }
+        None => None,
+    };
+
+    match removed {
+        Some(removed) if removed.len() == 1 => {
+            let track_info = removed[0].track.info.as_ref().unwrap();
+            reply(
+                ctx,
+                format!(
+                    "Removed: [{}]({})",
+                    chop_str(track_info.title.as_str(), MAX_SINGLE_ENTRY_LENGTH),
+                    track_info.uri
+                ),
+            )
+            .await?;
+        }
+        Some(removed) => {
+            reply(ctx, format!("Removed {} tracks.", removed.len())).await?;
+        }
+        None => {
+            reply(ctx, "There are no upcoming tracks at that position.").await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Move a track to another position in the queue.
+///
+/// Positions are the ones shown by `queue`.
+///
+/// Usage: `move 7 2`
+#[command(slash_command, rename = "move", aliases("mv"))]
+pub async fn move_track(
+    ctx: PoiseContext<'_>,
+    #[description = "Position of the track to move."] from: usize,
+    #[description = "Position to move the track to."] to: usize,
+) -> Result<(), Error> {
+    let guild = guild_check(ctx).await?;
+
+    if !dj_check(ctx, &guild).await? {
+        return Ok(());
+    }
+
+    let lava_client = &ctx.data().lavalink.client(guild.id.0).await;
+
+    let moved = match lava_client.nodes().await.get_mut(&guild.id.0) {
+        Some(mut node) => {
+            let start = usize::from(node.now_playing.is_some());
+            let queue_len = node.queue.len();
+            if from > start && to > start && from <= queue_len && to <= queue_len {
+                let track = node.queue.remove(from - 1);
+                node.queue.insert(to - 1, track.clone());
+                Some(track)
+            } else {
+                None
+            }
+        }
+        None => None,
+    };
+
+    if let Some(track) = moved {
+        let track_info = track.track.info.as_ref().unwrap();
+        reply(
+            ctx,
+            format!(
+                "Moved [{}]({}) to position {}.",
+                chop_str(track_info.title.as_str(), MAX_SINGLE_ENTRY_LENGTH),
+                track_info.uri,
+                to
+            ),
+        )
+        .await?;
+    } else {
+        reply(ctx, "There are no upcoming tracks at those positions.").await?;
+    }
+
+    Ok(())
+}
+
+/// Skip ahead to a track in the queue.
+///
+/// Every track before the given position, including the current one, is
+/// skipped. Positions are the ones shown by `queue`.
+#[command(slash_command, rename = "skipto", aliases("st"))]
+pub async fn skip_to(
+    ctx: PoiseContext<'_>,
+    #[description = "Position of the track to skip to."] position: usize,
+) -> Result<(), Error> {
+    let guild = guild_check(ctx).await?;
+
+    if !dj_check(ctx, &guild).await? {
+        return Ok(());
+    }
+
+    let lava_client = &ctx.data().lavalink.client(guild.id.0).await;
+
+    let skipped = match lava_client.nodes().await.get_mut(&guild.id.0) {
+        Some(mut node) => {
+            let start = usize::from(node.now_playing.is_some());
+            if position > start && position <= node.queue.len() {
+                let target = node.queue[position - 1].clone();
+                let skipped = node.queue.drain(start..position - 1).collect::<Vec<_>>();
+                Some((target, skipped, start == 1))
+            } else {
+                None
+            }
+        }
+        None => None,
+    };
+
+    let (target, mut skipped, is_playing) = match skipped {
+        Some(skipped) => skipped,
+        None => {
+            reply(ctx, "There is no upcoming track at that position.").await?;
+            return Ok(());
+        }
+    };
+
+    // Only the playing track is left in front of the target
+    if is_playing {
+        if let Some(current) = lava_client.skip(guild.id.0).await {
+            skipped.insert(0, current);
+        }
+    }
+
+    // When looping the queue, skipped tracks go around again as well
+    let loop_mode = {
+        let data = ctx.discord().data.read().await;
+        let loop_mode_map = data
+            .get::<LoopModeMap>()
+            .expect("Expected LoopModeMap in TypeMap")
+            .read()
+            .await;
+        loop_mode_map.get(&guild.id.0).copied().unwrap_or_default()
+    };
+    if loop_mode == LoopMode::Queue {
+        for track in skipped {
+            enqueue(lava_client, guild.id.0, track).await?;
+        }
+    }
+
+    let track_info = target.track.info.as_ref().unwrap();
+    reply(
+        ctx,
+        format!(
+            "Skipped to: [{}]({})",
+            chop_str(track_info.title.as_str(), MAX_SINGLE_ENTRY_LENGTH),
+            track_info.uri
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+// Changes the filters of a guild and sends them to its player right away.
+async fn update_filters(
+    ctx: PoiseContext<'_>,
+    guild_id: u64,
+    update: impl FnOnce(&mut AudioFilters),
+) -> Result<AudioFilters, Error> {
+    let filters = {
+        let data = ctx.discord().data.read().await;
+        let mut filters_map = data
+            .get::<FiltersMap>()
+            .expect("Expected FiltersMap in TypeMap")
+            .write()
+            .await;
+        let filters = filters_map.entry(guild_id).or_default();
+        update(filters);
+        filters.clone()
+    };
+
+    apply_filters(
+        &ctx.data().lavalink.client(guild_id).await,
+        guild_id,
+        &filters,
+    )
+    .await?;
+
+    Ok(filters)
+}
+
+/// Change the playback volume.
+///
+/// The volume is given in percent, between 0 and 200. Run without a value to
+/// see the current volume.
+#[command(slash_command, aliases("vol", "v"))]
+pub async fn volume(
+    ctx: PoiseContext<'_>,
+    #[description = "Volume in percent."] volume: Option<u16>,
+) -> Result<(), Error> {
+    let guild = guild_check(ctx).await?;
+
+    let volume = match volume {
+        Some(volume) if volume <= 200 => volume,
+        Some(_) => {
+            reply(ctx, "The volume has to be between 0 and 200.").await?;
+            return Ok(());
+        }
+        None => {
+            let data = ctx.discord().data.read().await;
+            let filters_map = data
+                .get::<FiltersMap>()
+                .expect("Expected FiltersMap in TypeMap")
+                .read()
+                .await;
+            let volume = filters_map
+                .get(&guild.id.0)
+                .and_then(|filters| filters.volume)
+                .unwrap_or(100);
+            reply(ctx, format!("The volume is at {}%.", volume)).await?;
+            return Ok(());
+        }
+    };
+
+    update_filters(ctx, guild.id.0, |filters| {
+        filters.volume = if volume == 100 { None } else { Some(volume) };
+    })
+    .await?;
+    reply(ctx, format!("Set the volume to {}%.", volume)).await?;
+
+    Ok(())
+}
+
+/// Apply an equalizer preset.
+///
+/// The available presets are `bassboost`, `pop`, `rock`, `soft` and `treble`.
+/// Use `off` to flatten the equalizer again.
+#[command(slash_command, aliases("equalizer"))]
+pub async fn eq(
+    ctx: PoiseContext<'_>,
+    #[description = "Preset to apply, or off."] preset: String,
+) -> Result<(), Error> {
+    let guild = guild_check(ctx).await?;
+
+    let preset = match preset.trim() {
+        "off" | "flat" => None,
+        preset => match EqPreset::from_str(preset) {
+            Ok(preset) => Some(preset),
+            Err(_) => {
+                reply(
+                    ctx,
+                    "Invalid preset, use one of `bassboost`, `pop`, `rock`, `soft`, `treble` or `off`.",
+                )
+                .await?;
+                return Ok(());
+            }
+        },
+    };
+
+    update_filters(ctx, guild.id.0, |filters| filters.equalizer = preset).await?;
+    match preset {
+        Some(preset) => {
+            reply(
+                ctx,
+                format!("Applied the `{}` equalizer preset.", preset.to_string()),
+            )
+            .await?
+        }
+        None => reply(ctx, "Turned off the equalizer.").await?,
+    };
+
+    Ok(())
+}
+
+/// Toggle the bass boost equalizer preset.
+#[command(slash_command, aliases("bb"))]
+pub async fn bassboost(ctx: PoiseContext<'_>) -> Result<(), Error> {
+    let guild = guild_check(ctx).await?;
+
+    let filters = update_filters(ctx, guild.id.0, |filters| {
+        filters.equalizer = match filters.equalizer {
+            Some(EqPreset::Bassboost) => None,
+            _ => Some(EqPreset::Bassboost),
+        };
+    })
+    .await?;
+
+    if filters.equalizer.is_some() {
+        reply(ctx, "Bass boost is now on.").await?;
+    } else {
+        reply(ctx, "Bass boost is now off.").await?;
+    }
+
+    Ok(())
+}
+
+/// Toggle the nightcore filter, which speeds up and pitches up playback.
+#[command(slash_command, aliases("nc"))]
+pub async fn nightcore(ctx: PoiseContext<'_>) -> Result<(), Error> {
+    let guild = guild_check(ctx).await?;
+
+    let filters = update_filters(ctx, guild.id.0, |filters| {
+        filters.nightcore = !filters.nightcore;
+    })
+    .await?;
+
+    if filters.nightcore {
+        reply(ctx, "Nightcore is now on.").await?;
+    } else {
+        reply(ctx, "Nightcore is now off.").await?;
+    }
+
+    Ok(())
+}
+
+/// Toggle the 8D filter, which rotates the audio around your head.
+#[command(slash_command, rename = "8d", aliases("rotate"))]
+pub async fn eight_d(ctx: PoiseContext<'_>) -> Result<(), Error> {
+    let guild = guild_check(ctx).await?;
+
+    let filters = update_filters(ctx, guild.id.0, |filters| {
+        filters.rotation = !filters.rotation;
+    })
+    .await?;
+
+    if filters.rotation {
+        reply(ctx, "8D is now on.").await?;
+    } else {
+        reply(ctx, "8D is now off.").await?;
+    }
+
+    Ok(())
+}
+
+/// Show the active audio filters.
+///
+/// Use `filters reset` to turn all of them off.
+#[command(slash_command)]
+pub async fn filters(
+    ctx: PoiseContext<'_>,
+    #[description = "Turn off all filters."]
+    #[flag]
+    reset: bool,
+) -> Result<(), Error> {
+    let guild = guild_check(ctx).await?;
+
+    if reset {
+        {
+            let data = ctx.discord().data.read().await;
+            let mut filters_map = data
+                .get::<FiltersMap>()
+                .expect("Expected FiltersMap in TypeMap")
+                .write()
+                .await;
+            filters_map.remove(&guild.id.0);
+        }
+        apply_filters(
+            &ctx.data().lavalink.client(guild.id.0).await,
+            guild.id.0,
+            &AudioFilters::default(),
+        )
+        .await?;
+        reply(ctx, "Turned off all filters.").await?;
+        return Ok(());
+    }
+
+    let filters = {
+        let data = ctx.discord().data.read().await;
+        let filters_map = data
+            .get::<FiltersMap>()
+            .expect("Expected FiltersMap in TypeMap")
+            .read()
+            .await;
+        filters_map
+            .get(&guild.id.0)
+            .and_then(|filters| filters.describe())
+    };
+    match filters {
+        Some(filters) => reply(ctx, format!("Active filters: {}", filters)).await?,
+        None => reply(ctx, "No filters are active.").await?,
+    };
+
+    Ok(())
+}
+
+/// Set the DJ role.
+///
+/// With a DJ role set, only members with it or the Manage Server permission
+/// can use `leave`, `clear`, `seek`, `shuffle`, `remove`, `move` and `skipto`.
+/// Everyone else has to vote to skip tracks they didn't request. Run without a
+/// role to let everyone control the music again.
+#[command(
+    slash_command,
+    rename = "djrole",
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn dj_role(
+    ctx: PoiseContext<'_>,
+    #[description = "Role allowed to control the music."] role: Option<Role>,
+) -> Result<(), Error> {
+    let guild = guild_check(ctx).await?;
+
+    {
+        let data = ctx.discord().data.read().await;
+        let db = data.get::<PgPoolContainer>().unwrap();
+        database::Guild::new(db, guild.id)
+            .set_dj_role(role.as_ref().map(|role| role.id.0 as i64))
+            .await?;
+    }
+
+    match role {
+        Some(role) => reply(ctx, format!("{} is now the DJ role.", role.mention())).await?,
+        None => reply(ctx, "Everyone can control the music now.").await?,
+    };
+
+    Ok(())
+}
+
+/// Set the percentage of listeners that have to vote to skip a track.
+///
+/// Only the listeners in the bot's voice channel are counted. The default is
+/// 50%.
+#[command(
+    slash_command,
+    rename = "voteskip",
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn vote_skip_percent(
+    ctx: PoiseContext<'_>,
+    #[description = "Percentage of listeners needed, from 1 to 100."] percent: u8,
+) -> Result<(), Error> {
+    let guild = guild_check(ctx).await?;
+
+    if !(1..=100).contains(&percent) {
+        reply(ctx, "The percentage must be between 1 and 100.").await?;
+        return Ok(());
+    }
+
+    {
+        let data = ctx.discord().data.read().await;
+        let db = data.get::<PgPoolContainer>().unwrap();
+        database::Guild::new(db, guild.id)
+            .set_vote_skip_percent(percent as i32)
+            .await?;
+    }
+    reply(
+        ctx,
+        format!(
+            "Skipping a track now takes {}% of the listeners' votes.",
+            percent
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Set how long the bot stays in the voice channel while nothing is playing.
+///
+/// The bot also counts as idle when everyone left its voice channel, in which
+/// case playback is paused until someone comes back. The default is 15
+/// minutes.
+#[command(
+    slash_command,
+    rename = "idletimeout",
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn idle_timeout(
+    ctx: PoiseContext<'_>,
+    #[description = "Minutes to wait before leaving, from 1 to 1440."] minutes: u32,
+) -> Result<(), Error> {
+    let guild = guild_check(ctx).await?;
+
+    if !(1..=1440).contains(&minutes) {
+        reply(ctx, "The timeout must be between 1 and 1440 minutes.").await?;
+        return Ok(());
+    }
+
+    {
+        let data = ctx.discord().data.read().await;
+        let db = data.get::<PgPoolContainer>().unwrap();
+        database::Guild::new(db, guild.id)
+            .set_idle_timeout(minutes as i32 * 60)
+            .await?;
+    }
+    reply(
+        ctx,
+        format!(
+            "The bot now leaves after {} minute{} of inactivity.",
+            minutes,
+            if minutes != 1 { "s" } else { "" }
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Toggle 24/7 mode, in which the bot never leaves the voice channel on its
+/// own.
+#[command(
+    slash_command,
+    rename = "247",
+    aliases("alwayson"),
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn always_on(ctx: PoiseContext<'_>) -> Result<(), Error> {
+    let guild = guild_check(ctx).await?;
+
+    let always_on = {
+        let data = ctx.discord().data.read().await;
+        let db = data.get::<PgPoolContainer>().unwrap();
+        let db_guild = database::Guild::new(db, guild.id);
+        let always_on = !db_guild.get_settings().await?.always_on;
+        db_guild.set_always_on(always_on).await?;
+        always_on
+    };
+    reply(
+        ctx,
+        if always_on {
+            "24/7 mode enabled, the bot won't leave the voice channel on its own."
+        } else {
+            "24/7 mode disabled."
+        },
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Toggle autoplay.
+///
+/// With autoplay on, a related track is queued whenever the queue runs out,
+/// so the music keeps going until someone stops it.
+#[command(slash_command, aliases("radio"))]
+pub async fn autoplay(ctx: PoiseContext<'_>) -> Result<(), Error> {
+    let guild = guild_check(ctx).await?;
+
+    if !dj_check(ctx, &guild).await? {
+        return Ok(());
+    }
+
+    let autoplay = {
+        let data = ctx.discord().data.read().await;
+        let db = data.get::<PgPoolContainer>().unwrap();
+        let db_guild = database::Guild::new(db, guild.id);
+        let autoplay = !db_guild.get_settings().await?.autoplay;
+        db_guild.set_autoplay(autoplay).await?;
+        autoplay
+    };
+    reply(
+        ctx,
+        if autoplay {
+            "Autoplay enabled, related tracks are queued when the queue runs out."
+        } else {
+            "Autoplay disabled."
+        },
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Toggle the fair queue.
+///
+/// With the fair queue on, newly queued tracks are spread between the tracks
+/// of the other members instead of going to the back of the queue, so one
+/// long playlist can't hold up everyone else.
+#[command(slash_command, rename = "fairqueue", aliases("fair"))]
+pub async fn fair_queue(ctx: PoiseContext<'_>) -> Result<(), Error> {
+    let guild = guild_check(ctx).await?;
+
+    if !dj_check(ctx, &guild).await? {
+        return Ok(());
+    }
+
+    let fair_queue = {
+        let data = ctx.discord().data.read().await;
+        let db = data.get::<PgPoolContainer>().unwrap();
+        let db_guild = database::Guild::new(db, guild.id);
+        let fair_queue = !db_guild.get_settings().await?.fair_queue;
+        db_guild.set_fair_queue(fair_queue).await?;
+        fair_queue
+    };
+    reply(
+        ctx,
+        if fair_queue {
+            "Fair queue enabled, new tracks take turns between the members who queued them."
+        } else {
+            "Fair queue disabled."
+        },
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Show or change the server's music limits.
+///
+/// The available limits are `usertracks` (tracks one member can have in the
+/// queue), `length` (longest track that can be queued), `playlist` (tracks
+/// that can be queued at once) and `streams` (whether live streams can be
+/// played). Use `off` to remove a limit.
+///
+/// Run without arguments to see the current limits.
+///
+/// Usage:
+/// `musiclimits usertracks 10`
+/// `musiclimits length 10m`
+/// `musiclimits streams off`
+#[command(
+    slash_command,
+    rename = "musiclimits",
+    aliases("limits"),
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn music_limits(
+    ctx: PoiseContext<'_>,
+    #[description = "Either usertracks, length, playlist or streams."] limit: Option<String>,
+    #[description = "New value of the limit, or off."] value: Option<String>,
+) -> Result<(), Error> {
+    let guild = guild_check(ctx).await?;
+
+    let data = ctx.discord().data.read().await;
+    let db = data.get::<PgPoolContainer>().unwrap();
+    let db_guild = database::Guild::new(db, guild.id);
+    let mut settings = db_guild.get_settings().await?;
+
+    let (limit, value) = match (limit, value) {
+        (Some(limit), Some(value)) => (limit, value),
+        (None, _) => {
+            let describe = |limit: Option<i32>| match limit {
+                Some(limit) => limit.to_string(),
+                None => "no limit".to_owned(),
+            };
+            reply_embed(ctx, |e| {
+                e.title("Music Limits")
+                    .field(
+                        "Tracks per member:",
+                        describe(settings.max_user_tracks),
+                        true,
+                    )
+                    .field(
+                        "Track length:",
+                        match settings.max_track_length_secs {
+                            Some(secs) => display_time_span(secs as u64 * 1000),
+                            None => "no limit".to_owned(),
+                        },
+                        true,
+                    )
+                    .field(
+                        "Tracks at once:",
+                        describe(settings.max_playlist_size),
+                        true,
+                    )
+                    .field(
+                        "Live streams:",
+                        if settings.allow_streams {
+                            "allowed"
+                        } else {
+                            "not allowed"
+                        },
+                        true,
+                    )
+            })
+            .await?;
+            return Ok(());
+        }
+        (Some(_), None) => {
+            reply(ctx, "Please provide a value for the limit, or `off`.").await?;
+            return Ok(());
+        }
+    };
+    let value = value.trim();
+    let off = matches!(value, "off" | "none");
+    let parse_count = |value: &str| {
+        if off {
+            Some(None)
+        } else {
+            value
+                .parse::<u16>()
+                .ok()
+                .filter(|max| *max > 0)
+                .map(|max| Some(max as i32))
+        }
+    };
+
+    let valid = match limit.trim() {
+        "usertracks" | "tracks" => parse_count(value)
+            .map(|max| settings.max_user_tracks = max)
+            .is_some(),
+        "length" | "duration" => {
+            let max_length = if off {
+                Some(None)
+            } else {
+                parse_time(value)
+                    .filter(|max| max.as_secs() > 0 && max.as_secs() <= i32::MAX as u64)
+                    .map(|max| Some(max.as_secs() as i32))
+            };
+            max_length
+                .map(|max| settings.max_track_length_secs = max)
+                .is_some()
+        }
+        "playlist" | "playlists" => parse_count(value)
+            .map(|max| settings.max_playlist_size = max)
+            .is_some(),
+        "streams" => match value {
+            "on" | "allow" => {
+                settings.allow_streams = true;
+                true
+            }
+            "off" | "deny" => {
+                settings.allow_streams = false;
+                true
+            }
+            _ => false,
+        },
+        _ => {
+            reply(
+                ctx,
+                "Invalid limit, use one of `usertracks`, `length`, `playlist` or `streams`.",
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+    if !valid {
+        reply(ctx, "Invalid value for the limit.").await?;
+        return Ok(());
+    }
+
+    db_guild
+        .set_music_limits(
+            settings.max_user_tracks,
+            settings.max_track_length_secs,
+            settings.max_playlist_size,
+            settings.allow_streams,
+        )
+        .await?;
+    reply(ctx, "Updated the music limits.").await?;
+
+    Ok(())
+}
+
+/// Show the state of the Lavalink nodes and of the players on them.
+///
+/// Usage: `lavalink`
+#[command(prefix_command, owners_only, hide_in_help)]
+pub async fn lavalink(ctx: PoiseContext<'_>) -> Result<(), Error> {
+    let lavalink = &ctx.data().lavalink;
+    let mut idle_guilds = {
+        let data = ctx.discord().data.read().await;
+        let idle_hash_map = data
+            .get::<IdleGuildMap>()
+            .expect("Expected IdleGuildMap in TypeMap")
+            .read()
+            .await;
+        idle_hash_map.clone()
+    };
+
+    let mut node_fields = Vec::new();
+    for (node, stats) in lavalink.stats().await.into_iter().enumerate() {
+        let (stats, reported) = match stats {
+            Some(stats) => stats,
+            None => {
+                node_fields.push((format!("Node {}", node), "No stats yet.".to_owned()));
+                continue;
+            }
+        };
+        let frames = match &stats.frame_stats {
+            Some(frame_stats) => format!(
+                "{} sent, {} nulled, {} deficit",
+                frame_stats.sent, frame_stats.nulled, frame_stats.deficit
+            ),
+            None => "none sent".to_owned(),
+        };
+        node_fields.push((
+            format!(
+                "Node {}{}",
+                node,
+                if lavalink.is_healthy(node).await {
+                    ""
+                } else {
+                    " (down)"
+                }
+            ),
+            format!(
+                "**Players:** {} ({} playing)\n\
+                **CPU:** {:.1}% system, {:.1}% Lavalink, {} cores\n\
+                **Memory:** {} MiB used, {} MiB allocated\n\
+                **Frames:** {}\n\
+                **Uptime:** {}\n\
+                **Reported:** {} ago",
+                stats.players,
+                stats.playing_players,
+                stats.cpu.system_load * 100.0,
+                stats.cpu.lavalink_load * 100.0,
+                stats.cpu.cores,
+                stats.memory.used / 1024 / 1024,
+                stats.memory.allocated / 1024 / 1024,
+                frames,
+                format_seconds(stats.uptime as u64 / 1000),
+                format_seconds(reported.elapsed().as_secs()),
+            ),
+        ));
+    }
+
+    let mut players = Vec::new();
+    for (node, client) in lavalink.clients().iter().enumerate() {
+        for player in client.nodes().await.iter() {
+            let guild_id = *player.key();
+            let state = if player.now_playing.is_none() {
+                "stopped"
+            } else if player.is_paused {
+                "paused"
+            } else {
+                "playing"
+            };
+            players.push((
+                guild_id,
+                format!("node {}, {}, {} in queue", node, state, player.queue.len()),
+            ));
+        }
+    }
+    // Guilds the bot sits idle in without a player are worth knowing about too
+    for guild_id in idle_guilds.keys() {
+        if !players
+            .iter()
+            .any(|(player_guild_id, _)| player_guild_id == guild_id)
+        {
+            players.push((*guild_id, "no player".to_owned()));
+        }
+    }
+    players.sort_unstable_by_key(|(guild_id, _)| *guild_id);
+
+    let mut desc = String::new();
+    let players_len = players.len();
+    for (i, (guild_id, state)) in players.into_iter().enumerate() {
+        let name = GuildId(guild_id)
+            .name(ctx.discord())
+            .unwrap_or_else(|| guild_id.to_string());
+        desc.push_str(format!("**{}** - {}", escape_str(&name), state).as_str());
+        if let Some(idle_since) = idle_guilds.remove(&guild_id) {
+            desc.push_str(
+                format!(
+                    ", idle for {}",
+                    format_seconds(idle_since.elapsed().as_secs())
+                )
+                .as_str(),
+            );
+        }
+        if i < players_len - 1 {
+            desc.push('\n');
+            if desc.len() > DESCRIPTION_LENGTH_CUTOFF {
+                desc.push_str("*…the rest has been clipped*");
+                break;
+            }
+        }
+    }
+    if desc.is_empty() {
+        desc.push_str("There are no players.");
+    }
+
+    reply_embed(ctx, |e| {
+        e.title("Lavalink").description(desc);
+        for (name, value) in node_fields {
+            e.field(name, value, false);
+        }
+        e
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use lavalink_rs::model::Info;
+
+    use super::*;
+
+    fn queued(length: u64, is_stream: bool) -> TrackQueue {
+        TrackQueue {
+            track: Track {
+                track: String::new(),
+                info: Some(Info {
+                    identifier: String::new(),
+                    is_seekable: !is_stream,
+                    author: String::new(),
+                    length,
+                    is_stream,
+                    position: 0,
+                    title: String::new(),
+                    uri: String::new(),
+                }),
+            },
+            start_time: 0,
+            end_time: None,
+            requester: None,
+        }
+    }
+
+    #[test]
+    fn adds_up_start_times() {
+        let queue = vec![
+            queued(100_000, false),
+            queued(50_000, false),
+            queued(30_000, false),
+        ];
+        assert_eq!(
+            queue_start_times(&queue, Some(40_000)),
+            vec![Some(0), Some(60_000), Some(110_000), Some(140_000)]
+        );
+        assert_eq!(
+            queue_start_times(&queue, None),
+            vec![Some(0), Some(100_000), Some(150_000), Some(180_000)]
+        );
+        assert_eq!(
+            queue_start_times(&queue[..1], Some(120_000)),
+            vec![Some(0), Some(0)]
+        );
+        assert_eq!(queue_start_times(&[], None), vec![Some(0)]);
+    }
+
+    #[test]
+    fn stops_start_times_at_streams() {
+        let queue = vec![
+            queued(100_000, false),
+            queued(0, true),
+            queued(30_000, false),
+        ];
+        assert_eq!(
+            queue_start_times(&queue, Some(40_000)),
+            vec![Some(0), Some(60_000), None, None]
+        );
+        assert_eq!(
+            queue_start_times(&queue[1..], None),
+            vec![Some(0), None, None]
+        );
+    }
+}
@@ -0,0 +1,452 @@
+use poise::{
+    command,
+    serenity::model::{
+        guild::{Guild, Role},
+        id::RoleId,
+        misc::Mentionable,
+    },
+};
+use sqlx::PgPool;
+
+use crate::{
+    constants::{MAX_PLAYLIST_NAME_LENGTH, MAX_SINGLE_ENTRY_LENGTH, PLAYLIST_QUERY_PREFIX},
+    data::PgPoolContainer,
+    database::{self, Playlists, StoredTrack},
+    types::{Error, PoiseContext},
+    utils::{
+        discord::{guild_check, reply, reply_embed, reply_file},
+        helpers::{chop_str, escape_str},
+        playlist_files::{export_playlist, PlaylistFormat},
+    },
+};
+
+fn playlists_for<'a>(
+    db: &'a PgPool,
+    ctx: PoiseContext<'_>,
+    guild: &Guild,
+    shared: bool,
+) -> Playlists<'a> {
+    if shared {
+        Playlists::guild(db, guild.id)
+    } else {
+        Playlists::personal(db, ctx.author().id)
+    }
+}
+
+/// Personal playlists can always be edited by their owner, shared ones need
+/// the guild's playlist role or the Manage Server permission.
+async fn can_edit(
+    ctx: PoiseContext<'_>,
+    db: &PgPool,
+    guild: &Guild,
+    shared: bool,
+) -> Result<bool, Error> {
+    if !shared {
+        return Ok(true);
+    }
+
+    let member = guild.member(ctx.discord(), ctx.author().id).await?;
+    if guild.member_permissions(&member).manage_guild() {
+        return Ok(true);
+    }
+
+    let settings = database::Guild::new(db, guild.id).get_settings().await?;
+    Ok(match settings.playlist_role_id {
+        Some(role_id) => member.roles.contains(&RoleId(role_id as u64)),
+        None => false,
+    })
+}
+
+async fn check_name(ctx: PoiseContext<'_>, name: &str) -> Result<bool, Error> {
+    if name.is_empty() || name.chars().count() > MAX_PLAYLIST_NAME_LENGTH {
+        reply(
+            ctx,
+            format!(
+                "Playlist names must be between 1 and {} characters long.",
+                MAX_PLAYLIST_NAME_LENGTH
+            ),
+        )
+        .await?;
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+/// Save the whole queue as a playlist.
+///
+/// An existing playlist with the same name is overwritten. Add `guild` in
+/// front of the name to save it as a playlist shared by the whole server.
+///
+/// Load it again with `play playlist:<name>`.
+///
+/// Usage:
+/// `playlistsave chill vibes`
+/// `playlistsave guild chill vibes`
+#[command(slash_command, rename = "playlistsave", aliases("plsave"))]
+pub async fn playlist_save(
+    ctx: PoiseContext<'_>,
+    #[description = "Use the server's playlists."]
+    #[flag]
+    guild: bool,
+    #[rest]
+    #[description = "Name of the playlist."]
+    name: String,
+) -> Result<(), Error> {
+    let discord_guild = guild_check(ctx).await?;
+    let name = name.trim();
+    if !check_name(ctx, name).await? {
+        return Ok(());
+    }
+
+    let tracks: Vec<StoredTrack> = ctx
+        .data()
+        .lavalink
+        .client(discord_guild.id.0)
+        .await
+        .nodes()
+        .await
+        .get(&discord_guild.id.0)
+        .map(|node| {
+            node.queue
+                .iter()
+                .map(|queued| StoredTrack {
+                    requester: Some(ctx.author().id.0 as i64),
+                    ..StoredTrack::from(queued)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    if tracks.is_empty() {
+        reply(ctx, "Nothing is in the queue.").await?;
+        return Ok(());
+    }
+
+    let data = ctx.discord().data.read().await;
+    let db = data.get::<PgPoolContainer>().unwrap();
+    if !can_edit(ctx, db, &discord_guild, guild).await? {
+        reply(ctx, "You are not allowed to edit the server's playlists.").await?;
+        return Ok(());
+    }
+
+    playlists_for(db, ctx, &discord_guild, guild)
+        .save_tracks(name, &tracks, true)
+        .await?;
+    reply(
+        ctx,
+        format!(
+            "Saved {} tracks to playlist `{}`.",
+            tracks.len(),
+            escape_str(name)
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Add the track that is currently playing to a playlist.
+///
+/// The playlist is created if it doesn't exist yet. Add `guild` in front of
+/// the name to use a playlist shared by the whole server.
+///
+/// Usage:
+/// `playlistadd chill vibes`
+/// `playlistadd guild chill vibes`
+#[command(slash_command, rename = "playlistadd", aliases("pladd"))]
+pub async fn playlist_add(
+    ctx: PoiseContext<'_>,
+    #[description = "Use the server's playlists."]
+    #[flag]
+    guild: bool,
+    #[rest]
+    #[description = "Name of the playlist."]
+    name: String,
+) -> Result<(), Error> {
+    let discord_guild = guild_check(ctx).await?;
+    let name = name.trim();
+    if !check_name(ctx, name).await? {
+        return Ok(());
+    }
+
+    let now_playing = ctx
+        .data()
+        .lavalink
+        .client(discord_guild.id.0)
+        .await
+        .nodes()
+        .await
+        .get(&discord_guild.id.0)
+        .and_then(|node| node.now_playing.clone());
+    let track = match now_playing {
+        Some(now_playing) => StoredTrack {
+            requester: Some(ctx.author().id.0 as i64),
+            ..StoredTrack::from(&now_playing)
+        },
+        None => {
+            reply(ctx, "Nothing is playing at the moment.").await?;
+            return Ok(());
+        }
+    };
+
+    let data = ctx.discord().data.read().await;
+    let db = data.get::<PgPoolContainer>().unwrap();
+    if !can_edit(ctx, db, &discord_guild, guild).await? {
+        reply(ctx, "You are not allowed to edit the server's playlists.").await?;
+        return Ok(());
+    }
+
+    playlists_for(db, ctx, &discord_guild, guild)
+        .save_tracks(name, std::slice::from_ref(&track), false)
+        .await?;
+    reply(
+        ctx,
+        format!(
+            "Added [{}]({}) to playlist `{}`.",
+            chop_str(track.title.as_str(), MAX_SINGLE_ENTRY_LENGTH),
+            track.uri,
+            escape_str(name)
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// List your playlists, or the server's with `guild`.
+#[command(slash_command, aliases("pls"))]
+pub async fn playlists(
+    ctx: PoiseContext<'_>,
+    #[description = "Use the server's playlists."]
+    #[flag]
+    guild: bool,
+) -> Result<(), Error> {
+    let discord_guild = guild_check(ctx).await?;
+
+    let data = ctx.discord().data.read().await;
+    let db = data.get::<PgPoolContainer>().unwrap();
+    let playlists = playlists_for(db, ctx, &discord_guild, guild).list().await?;
+
+    if playlists.is_empty() {
+        reply(ctx, "There are no saved playlists.").await?;
+        return Ok(());
+    }
+
+    let mut desc = String::new();
+    for playlist in &playlists {
+        desc.push_str(
+            format!(
+                "`{}` - {} track{}\n",
+                escape_str(&playlist.name),
+                playlist.track_count,
+                if playlist.track_count != 1 { "s" } else { "" }
+            )
+            .as_str(),
+        );
+    }
+    reply_embed(ctx, |e| {
+        e.title(if guild {
+            "Server Playlists"
+        } else {
+            "Your Playlists"
+        })
+        .description(desc)
+        .footer(|f| {
+            f.text(format!(
+                "Play one with: play {}<name>",
+                PLAYLIST_QUERY_PREFIX
+            ))
+        })
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Export a playlist as a file.
+///
+/// The file is an M3U playlist, or a JSON file when `json` is added. Either
+/// one can be queued by attaching it to `play`, also in other servers.
+///
+/// Usage:
+/// `playlistexport chill vibes`
+/// `playlistexport guild json chill vibes`
+#[command(slash_command, rename = "playlistexport", aliases("plexport"))]
+pub async fn playlist_export(
+    ctx: PoiseContext<'_>,
+    #[description = "Use the server's playlists."]
+    #[flag]
+    guild: bool,
+    #[description = "Export as JSON instead of M3U."]
+    #[flag]
+    json: bool,
+    #[rest]
+    #[description = "Name of the playlist."]
+    name: String,
+) -> Result<(), Error> {
+    let discord_guild = guild_check(ctx).await?;
+    let name = name.trim();
+
+    let tracks = {
+        let data = ctx.discord().data.read().await;
+        let db = data.get::<PgPoolContainer>().unwrap();
+        playlists_for(db, ctx, &discord_guild, guild)
+            .get_tracks(name)
+            .await?
+    };
+    let tracks = match tracks {
+        Some(tracks) => tracks,
+        None => {
+            reply(ctx, format!("No playlist named `{}`.", escape_str(name))).await?;
+            return Ok(());
+        }
+    };
+
+    let format = PlaylistFormat::from_flag(json);
+    let file = export_playlist(format, &tracks)?;
+    reply_file(
+        ctx,
+        format!(
+            "Exported {} tracks from playlist `{}`.",
+            tracks.len(),
+            escape_str(name)
+        ),
+        format!(
+            "{}.{}",
+            name.replace(|c: char| !c.is_alphanumeric() && c != '-', "_"),
+            format.extension()
+        ),
+        file.into_bytes(),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Rename a playlist.
+///
+/// Usage:
+/// `playlistrename "old name" "new name"`
+/// `playlistrename guild "old name" "new name"`
+#[command(slash_command, rename = "playlistrename", aliases("plrename"))]
+pub async fn playlist_rename(
+    ctx: PoiseContext<'_>,
+    #[description = "Use the server's playlists."]
+    #[flag]
+    guild: bool,
+    #[description = "Current name of the playlist."] name: String,
+    #[description = "New name of the playlist."] new_name: String,
+) -> Result<(), Error> {
+    let discord_guild = guild_check(ctx).await?;
+    let (name, new_name) = (name.trim(), new_name.trim());
+    if !check_name(ctx, new_name).await? {
+        return Ok(());
+    }
+
+    let data = ctx.discord().data.read().await;
+    let db = data.get::<PgPoolContainer>().unwrap();
+    if !can_edit(ctx, db, &discord_guild, guild).await? {
+        reply(ctx, "You are not allowed to edit the server's playlists.").await?;
+        return Ok(());
+    }
+
+    let playlists = playlists_for(db, ctx, &discord_guild, guild);
+    if playlists.get_id(new_name).await?.is_some() {
+        reply(
+            ctx,
+            format!(
+                "A playlist named `{}` already exists.",
+                escape_str(new_name)
+            ),
+        )
+        .await?;
+    } else if playlists.rename(name, new_name).await? {
+        reply(
+            ctx,
+            format!(
+                "Renamed playlist `{}` to `{}`.",
+                escape_str(name),
+                escape_str(new_name)
+            ),
+        )
+        .await?;
+    } else {
+        reply(ctx, format!("No playlist named `{}`.", escape_str(name))).await?;
+    }
+
+    Ok(())
+}
+
+/// Delete a playlist.
+///
+/// Usage:
+/// `playlistdelete chill vibes`
+/// `playlistdelete guild chill vibes`
+#[command(slash_command, rename = "playlistdelete", aliases("pldelete"))]
+pub async fn playlist_delete(
+    ctx: PoiseContext<'_>,
+    #[description = "Use the server's playlists."]
+    #[flag]
+    guild: bool,
+    #[rest]
+    #[description = "Name of the playlist."]
+    name: String,
+) -> Result<(), Error> {
+    let discord_guild = guild_check(ctx).await?;
+    let name = name.trim();
+
+    let data = ctx.discord().data.read().await;
+    let db = data.get::<PgPoolContainer>().unwrap();
+    if !can_edit(ctx, db, &discord_guild, guild).await? {
+        reply(ctx, "You are not allowed to edit the server's playlists.").await?;
+        return Ok(());
+    }
+
+    match playlists_for(db, ctx, &discord_guild, guild)
+        .delete(name)
+        .await?
+    {
+        Some(_) => reply(ctx, format!("Deleted playlist `{}`.", escape_str(name))).await?,
+        None => reply(ctx, format!("No playlist named `{}`.", escape_str(name))).await?,
+    };
+
+    Ok(())
+}
+
+/// Set the role that is allowed to edit the server's playlists.
+///
+/// Members with the Manage Server permission can always edit them. Run
+/// without a role to only allow those.
+#[command(
+    slash_command,
+    rename = "playlistrole",
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn playlist_role(
+    ctx: PoiseContext<'_>,
+    #[description = "Role allowed to edit server playlists."] role: Option<Role>,
+) -> Result<(), Error> {
+    let guild = guild_check(ctx).await?;
+
+    let data = ctx.discord().data.read().await;
+    let db = data.get::<PgPoolContainer>().unwrap();
+    database::Guild::new(db, guild.id)
+        .set_playlist_role(role.as_ref().map(|role| role.id.0 as i64))
+        .await?;
+
+    match role {
+        Some(role) => {
+            reply(
+                ctx,
+                format!("{} can now edit the server's playlists.", role.mention()),
+            )
+            .await?
+        }
+        None => reply(
+            ctx,
+            "Only members with the Manage Server permission can edit the server's playlists now.",
+        )
+        .await?,
+    };
+
+    Ok(())
+}
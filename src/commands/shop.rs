@@ -0,0 +1,415 @@
+use poise::serenity::model::{
+	guild::Role,
+	id::{GuildId, RoleId, UserId},
+	misc::Mentionable,
+	user::User,
+};
+
+use crate::{
+	constants::{DESCRIPTION_LENGTH_CUTOFF, MAX_ITEM_NAME_LENGTH, SHOP_SELL_PERCENT},
+	data::PgPoolContainer,
+	database::Guild,
+	types::{Error, PoiseContext},
+	utils::{
+		discord::{guild_check, reply_embed, reply_plain},
+		helpers::escape_str,
+	},
+};
+use tracing::error;
+
+// Gives or takes away the role that comes with an item. Failing to do so
+// shouldn't undo the purchase, so it's only reported.
+async fn set_item_role(
+	ctx: PoiseContext<'_>,
+	guild_id: GuildId,
+	user_id: UserId,
+	role_id: i64,
+	grant: bool,
+) -> bool {
+	let mut member = match guild_id.member(ctx.discord(), user_id).await {
+		Ok(member) => member,
+		Err(why) => {
+			error!("Failed to get member: {}", why);
+			return false;
+		}
+	};
+	let result = if grant {
+		member.add_role(ctx.discord(), RoleId(role_id as u64)).await
+	} else {
+		member
+			.remove_role(ctx.discord(), RoleId(role_id as u64))
+			.await
+	};
+	if let Err(why) = result {
+		error!("Failed to update item role: {}", why);
+		return false;
+	}
+	true
+}
+
+/// Show the items for sale.
+#[poise::command(slash_command)]
+pub async fn shop(ctx: PoiseContext<'_>) -> Result<(), Error> {
+	let guild = guild_check(ctx).await?;
+	let data = ctx.discord().data.read().await;
+	let db = data.get::<PgPoolContainer>().unwrap();
+	let items = Guild::new(db, guild.id).get_shop_items().await?;
+	if items.is_empty() {
+		reply_plain(ctx, "The shop is empty").await?;
+		return Ok(());
+	}
+
+	let mut desc = String::new();
+	let items_len = items.len();
+	for (i, item) in items.iter().enumerate() {
+		desc.push_str(format!("**{}** - {} cowoins", escape_str(&item.name), item.price).as_str());
+		match item.stock {
+			Some(0) => desc.push_str(", sold out"),
+			Some(stock) => desc.push_str(format!(", {} left", stock).as_str()),
+			None => {}
+		}
+		if let Some(role_id) = item.role_id {
+			desc.push_str(format!(", gives {}", RoleId(role_id as u64).mention()).as_str());
+		}
+		if item.consumable {
+			desc.push_str(", consumable");
+		}
+		if i < items_len - 1 {
+			desc.push('\n');
+			if desc.len() > DESCRIPTION_LENGTH_CUTOFF {
+				desc.push_str("*…the rest has been clipped*");
+				break;
+			}
+		}
+	}
+	reply_embed(ctx, |e| {
+		e.title("Shop");
+		e.description(desc)
+	})
+	.await?;
+
+	Ok(())
+}
+
+/// Put a new item up for sale.
+///
+/// Items without stock can be bought without limit. Buying an item with a
+/// role gives the buyer that role, and consumable items get used up with
+/// `use`.
+///
+/// Usage: `shopadd Cookie 100 50 consumable` or `shopadd VIP 5000 @VIP`
+#[poise::command(
+	slash_command,
+	rename = "shopadd",
+	required_permissions = "MANAGE_GUILD"
+)]
+pub async fn shop_add(
+	ctx: PoiseContext<'_>,
+	#[description = "Name of the item"] name: String,
+	#[description = "Price of the item"] price: i64,
+	#[description = "How many can be bought"] stock: Option<u32>,
+	#[description = "Role given to buyers"] role: Option<Role>,
+	#[description = "Whether the item gets used up"]
+	#[flag]
+	consumable: bool,
+) -> Result<(), Error> {
+	let guild = guild_check(ctx).await?;
+	if name.is_empty() || name.chars().count() > MAX_ITEM_NAME_LENGTH {
+		reply_plain(
+			ctx,
+			format!(
+				"Item names must be between 1 and {} characters long",
+				MAX_ITEM_NAME_LENGTH
+			),
+		)
+		.await?;
+		return Ok(());
+	}
+	if price < 0 {
+		reply_plain(ctx, "Can't sell items for the given price").await?;
+		return Ok(());
+	}
+
+	let added = {
+		let data = ctx.discord().data.read().await;
+		let db = data.get::<PgPoolContainer>().unwrap();
+		Guild::new(db, guild.id)
+			.add_shop_item(
+				&name,
+				price,
+				stock.map(|stock| stock.min(i32::MAX as u32) as i32),
+				role.as_ref().map(|role| role.id.0 as i64),
+				consumable,
+			)
+			.await?
+	};
+	if added {
+		reply_plain(ctx, format!("Added {} to the shop", escape_str(&name))).await?;
+	} else {
+		reply_plain(
+			ctx,
+			format!("There already is an item called {}", escape_str(&name)),
+		)
+		.await?;
+	}
+
+	Ok(())
+}
+
+/// Take an item out of the shop, and out of everyone's inventory along with
+/// the role it gave.
+///
+/// Usage: `shopremove Cookie`
+#[poise::command(
+	slash_command,
+	rename = "shopremove",
+	required_permissions = "MANAGE_GUILD"
+)]
+pub async fn shop_remove(
+	ctx: PoiseContext<'_>,
+	#[rest]
+	#[description = "Name of the item"]
+	name: String,
+) -> Result<(), Error> {
+	let guild = guild_check(ctx).await?;
+	let removed = {
+		let data = ctx.discord().data.read().await;
+		let db = data.get::<PgPoolContainer>().unwrap();
+		Guild::new(db, guild.id).remove_shop_item(&name).await?
+	};
+	let item = match removed {
+		Some(item) => item,
+		None => {
+			reply_plain(ctx, format!("There's no item called {}", escape_str(&name))).await?;
+			return Ok(());
+		}
+	};
+	let mut content = format!("Removed {} from the shop", escape_str(&name));
+	if let Some(role_id) = item.role_id {
+		let mut revoked = true;
+		for holder in item.holders {
+			revoked &= set_item_role(ctx, guild.id, UserId(holder as u64), role_id, false).await;
+		}
+		if !revoked {
+			content.push_str("\nI couldn't take the role that came with it from everyone");
+		}
+	}
+	reply_plain(ctx, content).await?;
+
+	Ok(())
+}
+
+/// Buy an item from the shop.
+///
+/// Usage: `buy Cookie`
+#[poise::command(slash_command)]
+pub async fn buy(
+	ctx: PoiseContext<'_>,
+	#[rest]
+	#[description = "Name of the item"]
+	name: String,
+) -> Result<(), Error> {
+	let guild = guild_check(ctx).await?;
+	let member_id = ctx.author().id;
+	let db = {
+		let data = ctx.discord().data.read().await;
+		data.get::<PgPoolContainer>().unwrap().clone()
+	};
+	let db_guild = Guild::new(&db, guild.id);
+	let item = match db_guild.get_shop_item(&name).await? {
+		Some(item) => item,
+		None => {
+			reply_plain(ctx, format!("There's no item called {}", escape_str(&name))).await?;
+			return Ok(());
+		}
+	};
+	let member = match db_guild.get_member(member_id).await? {
+		Some(member) => member,
+		None => {
+			reply_plain(ctx, format!("Could not find user with id: {}", member_id)).await?;
+			return Ok(());
+		}
+	};
+	if item.stock == Some(0) {
+		reply_plain(ctx, format!("{} is sold out", escape_str(&item.name))).await?;
+		return Ok(());
+	}
+	if item.price > member.coins {
+		reply_plain(ctx, "You don't have enough balance").await?;
+		return Ok(());
+	}
+
+	// The stock or the balance might have changed since they were checked above
+	let new_balance = match db_guild.buy_shop_item(member_id, &item).await? {
+		Some(new_balance) => new_balance,
+		None => {
+			reply_plain(
+				ctx,
+				format!(
+					"{} sold out or you can't afford it anymore",
+					escape_str(&item.name)
+				),
+			)
+			.await?;
+			return Ok(());
+		}
+	};
+	let mut content = format!(
+		"You bought {} for {} cowoins\nYou have {} cowoins now",
+		escape_str(&item.name),
+		item.price,
+		new_balance
+	);
+	if let Some(role_id) = item.role_id {
+		if !set_item_role(ctx, guild.id, member_id, role_id, true).await {
+			content.push_str("\nI couldn't give you the role that comes with it, ask an admin");
+		}
+	}
+	reply_plain(ctx, content).await?;
+
+	Ok(())
+}
+
+/// Sell an item back to the shop for part of its price.
+///
+/// Usage: `sell Cookie`
+#[poise::command(slash_command)]
+pub async fn sell(
+	ctx: PoiseContext<'_>,
+	#[rest]
+	#[description = "Name of the item"]
+	name: String,
+) -> Result<(), Error> {
+	let guild = guild_check(ctx).await?;
+	let member_id = ctx.author().id;
+	let db = {
+		let data = ctx.discord().data.read().await;
+		data.get::<PgPoolContainer>().unwrap().clone()
+	};
+	let db_guild = Guild::new(&db, guild.id);
+	let item = match db_guild.get_shop_item(&name).await? {
+		Some(item) => item,
+		None => {
+			reply_plain(ctx, format!("There's no item called {}", escape_str(&name))).await?;
+			return Ok(());
+		}
+	};
+
+	let refund = (item.price as i128 * SHOP_SELL_PERCENT as i128 / 100) as i64;
+	let (new_balance, quantity) = match db_guild.sell_shop_item(member_id, &item, refund).await? {
+		Some(sold) => sold,
+		None => {
+			reply_plain(ctx, format!("You don't have {}", escape_str(&item.name))).await?;
+			return Ok(());
+		}
+	};
+	// The role goes with the last one of the item
+	if let (Some(role_id), 0) = (item.role_id, quantity) {
+		set_item_role(ctx, guild.id, member_id, role_id, false).await;
+	}
+	reply_plain(
+		ctx,
+		format!(
+			"You sold {} for {} cowoins\nYou have {} cowoins now",
+			escape_str(&item.name),
+			refund,
+			new_balance
+		),
+	)
+	.await?;
+
+	Ok(())
+}
+
+/// Use up one of your consumable items.
+///
+/// Usage: `use Cookie`
+#[poise::command(slash_command, rename = "use")]
+pub async fn use_item(
+	ctx: PoiseContext<'_>,
+	#[rest]
+	#[description = "Name of the item"]
+	name: String,
+) -> Result<(), Error> {
+	let guild = guild_check(ctx).await?;
+	let member_id = ctx.author().id;
+	let db = {
+		let data = ctx.discord().data.read().await;
+		data.get::<PgPoolContainer>().unwrap().clone()
+	};
+	let db_guild = Guild::new(&db, guild.id);
+	let item = match db_guild.get_shop_item(&name).await? {
+		Some(item) => item,
+		None => {
+			reply_plain(ctx, format!("There's no item called {}", escape_str(&name))).await?;
+			return Ok(());
+		}
+	};
+	if !item.consumable {
+		reply_plain(ctx, format!("{} can't be used up", escape_str(&item.name))).await?;
+		return Ok(());
+	}
+
+	let quantity = match db_guild.use_inventory_item(member_id, item.id).await? {
+		Some(quantity) => quantity,
+		None => {
+			reply_plain(ctx, format!("You don't have {}", escape_str(&item.name))).await?;
+			return Ok(());
+		}
+	};
+	if let (Some(role_id), 0) = (item.role_id, quantity) {
+		set_item_role(ctx, guild.id, member_id, role_id, false).await;
+	}
+	reply_plain(
+		ctx,
+		format!(
+			"You used {}, you have {} left",
+			escape_str(&item.name),
+			quantity
+		),
+	)
+	.await?;
+
+	Ok(())
+}
+
+/// Show the items you own.
+///
+/// Usage: `inventory` or `inventory @user`
+#[poise::command(slash_command, aliases("inv"))]
+pub async fn inventory(
+	ctx: PoiseContext<'_>,
+	#[description = "Whose inventory to show"] user: Option<User>,
+) -> Result<(), Error> {
+	let guild = guild_check(ctx).await?;
+	let user = user.as_ref().unwrap_or_else(|| ctx.author());
+	let data = ctx.discord().data.read().await;
+	let db = data.get::<PgPoolContainer>().unwrap();
+	let items = Guild::new(db, guild.id).get_inventory(user.id).await?;
+	if items.is_empty() {
+		reply_plain(ctx, format!("{} has no items", escape_str(&user.name))).await?;
+		return Ok(());
+	}
+
+	let desc = items
+		.iter()
+		.map(|item| {
+			let mut line = format!("**{}** x{}", escape_str(&item.name), item.quantity);
+			if let Some(role_id) = item.role_id {
+				line.push_str(format!(", {}", RoleId(role_id as u64).mention()).as_str());
+			}
+			if item.consumable {
+				line.push_str(", consumable");
+			}
+			line
+		})
+		.collect::<Vec<_>>()
+		.join("\n");
+	reply_embed(ctx, |e| {
+		e.title(format!("Inventory of {}", escape_str(&user.name)));
+		e.description(desc)
+	})
+	.await?;
+
+	Ok(())
+}
@@ -31,11 +31,45 @@ lazy_static! {
 }
 
 pub const DAILY_AMOUNT: i64 = 1000;
+pub const DAILY_COOLDOWN_SECS: i32 = 86400;
+pub const DAILY_STREAK_BONUS_PERCENT: i64 = 10;
+pub const MAX_DAILY_STREAK_BONUS_DAYS: i32 = 10;
+pub const WEEKLY_MULTIPLIER: i64 = 5;
+pub const WEEKLY_COOLDOWN_SECS: i64 = 7 * 86400;
 pub const GAMBLE_MULTIPLIERS: [i64; 6] = [0, 1, 2, 3, 4, 5];
 pub const GAMBLE_WEIGHTS: [f64; 5] = [6.0, 2.0, 1.7, 0.2, 0.1];
+pub const TRANSACTIONS_LENGTH: i64 = 10;
+pub const PAY_CONFIRM_AMOUNT: i64 = 10_000;
+pub const PAY_CONFIRM_TIMEOUT_SECS: u64 = 30;
+pub const MAX_ITEM_NAME_LENGTH: usize = 40;
+pub const BLACKJACK_TIMEOUT_SECS: u64 = 60;
+pub const COINFLIP_CHALLENGE_TIMEOUT_SECS: u64 = 60;
+// Symbol, weight and how many times the bet three of them pay
+pub const SLOT_SYMBOLS: [(&str, u32, i64); 5] = [
+	("🍒", 5, 3),
+	("🍋", 4, 5),
+	("🔔", 3, 10),
+	("⭐", 2, 20),
+	("💎", 1, 50),
+];
+pub const ROULETTE_RED_NUMBERS: [u8; 18] = [
+	1, 3, 5, 7, 9, 12, 14, 16, 18, 19, 21, 23, 25, 27, 30, 32, 34, 36,
+];
+pub const SHOP_SELL_PERCENT: i64 = 50;
 pub const MAX_DESCRIPTION_LENGTH: usize = 2048;
 pub const DESCRIPTION_LENGTH_CUTOFF: usize = MAX_DESCRIPTION_LENGTH - 512;
 pub const MAX_LIST_ENTRY_LENGTH: usize = 60;
 pub const MAX_SINGLE_ENTRY_LENGTH: usize = 40;
+pub const MAX_PLAYLIST_NAME_LENGTH: usize = 40;
+pub const PLAYLIST_QUERY_PREFIX: &str = "playlist:";
 pub const UNKNOWN_TITLE: &str = "Unknown title";
 pub const LIVE_INDICATOR: &str = "🔴 **LIVE**";
+pub const SEARCH_RESULT_COUNT: usize = 5;
+pub const SEARCH_PICK_TIMEOUT_SECS: u64 = 30;
+pub const MAX_HISTORY_LENGTH: usize = 50;
+pub const QUEUE_PAGE_SIZE: usize = 10;
+pub const QUEUE_PAGE_TIMEOUT_SECS: u64 = 120;
+pub const SEEK_STEP_SECS: u64 = 10;
+pub const MAX_PLAYLIST_FILE_SIZE: u64 = 1024 * 1024;
+pub const MAX_PLAYLIST_FILE_TRACKS: usize = 500;
+pub const MUSIC_STATS_LENGTH: i64 = 5;
@@ -1,12 +1,18 @@
 use std::sync::{atomic::AtomicBool, Arc};
 
-use lavalink_rs::LavalinkClient;
 use poise::serenity_prelude::TypeMapKey;
 use songbird::Songbird;
 use sqlx::PgPool;
 use tokio::time::Instant;
 
-use crate::types::{IdleHashMap, LastMessageHashMap};
+use crate::{
+    lavalink::LavalinkNodes,
+    types::{
+        AlonePausedGuildSet, CurrentTrackHashMap, FiltersHashMap, HistoryHashMap, IdleHashMap,
+        LastMessageHashMap, LoopModeHashMap, NowPlayingMessageHashMap, PlayerPositionHashMap,
+        SkipVotesHashMap,
+    },
+};
 
 pub struct PgPoolContainer;
 
@@ -32,18 +38,68 @@ impl TypeMapKey for IdleGuildMap {
     type Value = IdleHashMap;
 }
 
+pub struct LoopModeMap;
+
+impl TypeMapKey for LoopModeMap {
+    type Value = LoopModeHashMap;
+}
+
+pub struct PlayerPositionMap;
+
+impl TypeMapKey for PlayerPositionMap {
+    type Value = PlayerPositionHashMap;
+}
+
+pub struct FiltersMap;
+
+impl TypeMapKey for FiltersMap {
+    type Value = FiltersHashMap;
+}
+
+pub struct NowPlayingMessageMap;
+
+impl TypeMapKey for NowPlayingMessageMap {
+    type Value = NowPlayingMessageHashMap;
+}
+
+pub struct SkipVotesMap;
+
+impl TypeMapKey for SkipVotesMap {
+    type Value = SkipVotesHashMap;
+}
+
+pub struct AlonePausedGuilds;
+
+impl TypeMapKey for AlonePausedGuilds {
+    type Value = AlonePausedGuildSet;
+}
+
+pub struct HistoryMap;
+
+impl TypeMapKey for HistoryMap {
+    type Value = HistoryHashMap;
+}
+
+pub struct CurrentTrackMap;
+
+impl TypeMapKey for CurrentTrackMap {
+    type Value = CurrentTrackHashMap;
+}
+
 pub struct Data {
     pub songbird: Arc<Songbird>,
-    pub lavalink: LavalinkClient,
+    pub lavalink: LavalinkNodes,
     pub is_services_running: AtomicBool,
+    pub is_music_restored: AtomicBool,
 }
 
 impl Data {
-    pub fn new(songbird: Arc<Songbird>, lavalink: LavalinkClient) -> Self {
+    pub fn new(songbird: Arc<Songbird>, lavalink: LavalinkNodes) -> Self {
         Self {
             songbird,
             lavalink,
             is_services_running: Default::default(),
+            is_music_restored: Default::default(),
         }
     }
 }
@@ -1,11 +1,14 @@
 use std::ops::Sub;
 
 use chrono::{DateTime, Duration, Utc};
+use lavalink_rs::model::{Info, Track, TrackQueue, UserId};
 use sqlx::{
-    postgres::{PgPool, PgQueryResult},
-    query, query_as,
+    postgres::{PgPool, PgQueryResult, Postgres},
+    query, query_as, query_scalar,
 };
 
+use crate::constants::{DAILY_AMOUNT, DAILY_COOLDOWN_SECS, MAX_HISTORY_LENGTH};
+
 pub struct Guild<'a> {
     pool: &'a PgPool,
     guild_id: i64,
@@ -17,6 +20,174 @@ pub struct Member {
     pub last_daily: DateTime<Utc>,
     pub coins: i64,
     pub guild_id: i64,
+    pub daily_streak: i32,
+    pub last_weekly: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub struct Transaction {
+    pub delta: i64,
+    pub reason: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub struct GuildSettings {
+    pub playlist_role_id: Option<i64>,
+    pub dj_role_id: Option<i64>,
+    pub vote_skip_percent: i32,
+    pub idle_timeout_secs: i32,
+    pub always_on: bool,
+    pub autoplay: bool,
+    pub max_user_tracks: Option<i32>,
+    pub max_track_length_secs: Option<i32>,
+    pub max_playlist_size: Option<i32>,
+    pub allow_streams: bool,
+    pub fair_queue: bool,
+    pub transfer_tax_percent: i32,
+    pub daily_amount: i64,
+    pub daily_cooldown_secs: i32,
+    pub max_bet: Option<i64>,
+}
+
+impl Default for GuildSettings {
+    fn default() -> Self {
+        Self {
+            playlist_role_id: None,
+            dj_role_id: None,
+            vote_skip_percent: 50,
+            idle_timeout_secs: 900,
+            always_on: false,
+            autoplay: false,
+            max_user_tracks: None,
+            max_track_length_secs: None,
+            max_playlist_size: None,
+            allow_streams: true,
+            fair_queue: false,
+            transfer_tax_percent: 0,
+            daily_amount: DAILY_AMOUNT,
+            daily_cooldown_secs: DAILY_COOLDOWN_SECS,
+            max_bet: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct MusicQueue {
+    pub guild_id: i64,
+    pub voice_channel_id: i64,
+    pub text_channel_id: Option<i64>,
+    pub position: i64,
+}
+
+#[derive(Debug)]
+pub struct StoredTrack {
+    pub track: String,
+    pub title: String,
+    pub author: String,
+    pub identifier: String,
+    pub uri: String,
+    pub length: i64,
+    pub is_stream: bool,
+    pub is_seekable: bool,
+    pub requester: Option<i64>,
+}
+
+impl From<&TrackQueue> for StoredTrack {
+    fn from(queued: &TrackQueue) -> Self {
+        let info = queued.track.info.as_ref().unwrap();
+        Self {
+            track: queued.track.track.clone(),
+            title: info.title.clone(),
+            author: info.author.clone(),
+            identifier: info.identifier.clone(),
+            uri: info.uri.clone(),
+            length: info.length as i64,
+            is_stream: info.is_stream,
+            is_seekable: info.is_seekable,
+            requester: queued.requester.map(|id| id.0 as i64),
+        }
+    }
+}
+
+impl From<StoredTrack> for TrackQueue {
+    fn from(stored: StoredTrack) -> Self {
+        Self {
+            track: Track {
+                track: stored.track,
+                info: Some(Info {
+                    identifier: stored.identifier,
+                    is_seekable: stored.is_seekable,
+                    author: stored.author,
+                    length: stored.length as u64,
+                    is_stream: stored.is_stream,
+                    position: 0,
+                    title: stored.title,
+                    uri: stored.uri,
+                }),
+            },
+            start_time: 0,
+            end_time: None,
+            requester: stored.requester.map(|id| UserId(id as u64)),
+        }
+    }
+}
+
+/// Playlists of a single user, or the ones shared by a whole guild.
+pub struct Playlists<'a> {
+    pool: &'a PgPool,
+    user_id: Option<i64>,
+    guild_id: Option<i64>,
+}
+
+#[derive(Debug)]
+pub struct MusicTotals {
+    pub plays: i64,
+    pub played_ms: i64,
+}
+
+#[derive(Debug)]
+pub struct TrackPlays {
+    pub title: String,
+    pub uri: String,
+    pub plays: i64,
+}
+
+#[derive(Debug)]
+pub struct ListenerPlays {
+    pub requester: i64,
+    pub plays: i64,
+    pub played_ms: i64,
+}
+
+#[derive(Debug)]
+pub struct ShopItem {
+    pub id: i64,
+    pub name: String,
+    pub price: i64,
+    pub stock: Option<i32>,
+    pub role_id: Option<i64>,
+    pub consumable: bool,
+}
+
+#[derive(Debug)]
+pub struct RemovedShopItem {
+    pub role_id: Option<i64>,
+    pub holders: Vec<i64>,
+}
+
+#[derive(Debug)]
+pub struct InventoryItem {
+    pub name: String,
+    pub quantity: i32,
+    pub role_id: Option<i64>,
+    pub consumable: bool,
+}
+
+#[derive(Debug)]
+pub struct PlaylistSummary {
+    pub name: String,
+    pub track_count: i64,
 }
 
 impl<'a> Guild<'a> {
@@ -91,7 +262,7 @@ impl<'a> Guild<'a> {
         Ok(query_as!(
             Member,
             r#"
-            SELECT id, last_daily, coins, guild_id
+            SELECT id, last_daily, coins, guild_id, daily_streak, last_weekly
             FROM members
             WHERE guild_id = $1
             "#,
@@ -105,7 +276,7 @@ impl<'a> Guild<'a> {
         Ok(query_as!(
             Member,
             r#"
-            SELECT id, last_daily, coins, guild_id
+            SELECT id, last_daily, coins, guild_id, daily_streak, last_weekly
             FROM members
             WHERE guild_id = $1 AND id = $2
             "#,
@@ -116,42 +287,1125 @@ impl<'a> Guild<'a> {
         .await?)
     }
 
-    pub async fn set_member_economy(
+    /// Adds `delta` to the member's coins and records it in the ledger.
+    /// Returns the new balance, or `None` if the member doesn't exist or
+    /// can't afford it.
+    pub async fn add_member_coins(
         &self,
         member_id: impl Into<i64>,
-        coins: i64,
-        last_daily: Option<DateTime<Utc>>,
+        delta: i64,
+        reason: &str,
+    ) -> anyhow::Result<Option<i64>> {
+        let member_id = member_id.into();
+        let mut tx = self.pool.begin().await?;
+        let coins = self.change_coins(&mut tx, member_id, delta).await?;
+        if coins.is_some() {
+            self.add_transaction(&mut tx, member_id, delta, reason)
+                .await?;
+            tx.commit().await?;
+        }
+        Ok(coins)
+    }
+
+    /// Moves `amount` coins from one member to another, of which `tax` coins
+    /// go nowhere. Returns the payer's new balance, or `None` if either
+    /// member doesn't exist or the payer can't afford it.
+    pub async fn transfer_member_coins(
+        &self,
+        from_member_id: impl Into<i64>,
+        to_member_id: impl Into<i64>,
+        amount: i64,
+        tax: i64,
+    ) -> anyhow::Result<Option<i64>> {
+        let from_member_id = from_member_id.into();
+        let to_member_id = to_member_id.into();
+        let mut tx = self.pool.begin().await?;
+        let coins = match self.change_coins(&mut tx, from_member_id, -amount).await? {
+            Some(coins) => coins,
+            None => return Ok(None),
+        };
+        if self
+            .change_coins(&mut tx, to_member_id, amount - tax)
+            .await?
+            .is_none()
+        {
+            return Ok(None);
+        }
+        self.add_transaction(
+            &mut tx,
+            from_member_id,
+            -amount,
+            &format!("pay to <@{}>", to_member_id),
+        )
+        .await?;
+        self.add_transaction(
+            &mut tx,
+            to_member_id,
+            amount,
+            &format!("pay from <@{}>", from_member_id),
+        )
+        .await?;
+        if tax > 0 {
+            self.add_transaction(&mut tx, to_member_id, -tax, "transfer tax")
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(Some(coins))
+    }
+
+    /// Settles a challenge whose stake the challenger has already paid. The
+    /// opponent pays their stake and the winner gets both. Returns `false`
+    /// if the opponent doesn't exist or can't afford it, in which case
+    /// nothing changes.
+    pub async fn settle_challenge(
+        &self,
+        opponent_id: impl Into<i64>,
+        winner_id: impl Into<i64>,
+        amount: i64,
+        reason: &str,
+    ) -> anyhow::Result<bool> {
+        let opponent_id = opponent_id.into();
+        let winner_id = winner_id.into();
+        let mut tx = self.pool.begin().await?;
+        if self
+            .change_coins(&mut tx, opponent_id, -amount)
+            .await?
+            .is_none()
+            || self
+                .change_coins(&mut tx, winner_id, amount * 2)
+                .await?
+                .is_none()
+        {
+            return Ok(false);
+        }
+        self.add_transaction(&mut tx, opponent_id, -amount, reason)
+            .await?;
+        self.add_transaction(&mut tx, winner_id, amount * 2, reason)
+            .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    // The check and the update happen in one statement, so concurrent
+    // changes can't push the balance below zero or overwrite each other
+    async fn change_coins(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Postgres>,
+        member_id: i64,
+        delta: i64,
+    ) -> anyhow::Result<Option<i64>> {
+        Ok(query_scalar!(
+            r#"
+            UPDATE members
+            SET coins = coins + $3
+            WHERE guild_id = $1 AND id = $2 AND coins + $3 >= 0
+            RETURNING coins
+            "#,
+            self.guild_id,
+            member_id,
+            delta,
+        )
+        .fetch_optional(tx)
+        .await?)
+    }
+
+    /// Gives the member their daily coins and sets their streak, unless
+    /// they claimed them again since `last_daily`. Returns the new balance
+    /// if they got them.
+    pub async fn claim_daily(
+        &self,
+        member_id: impl Into<i64>,
+        amount: i64,
+        streak: i32,
+        last_daily: DateTime<Utc>,
+    ) -> anyhow::Result<Option<i64>> {
+        let member_id = member_id.into();
+        let mut tx = self.pool.begin().await?;
+        let coins = query_scalar!(
+            r#"
+            UPDATE members
+            SET coins = coins + $3, daily_streak = $4, last_daily = now()
+            WHERE guild_id = $1 AND id = $2 AND last_daily = $5
+            RETURNING coins
+            "#,
+            self.guild_id,
+            member_id,
+            amount,
+            streak,
+            last_daily,
+        )
+        .fetch_optional(&mut tx)
+        .await?;
+        if coins.is_some() {
+            self.add_transaction(&mut tx, member_id, amount, "daily")
+                .await?;
+            tx.commit().await?;
+        }
+        Ok(coins)
+    }
+
+    /// Gives the member their weekly coins, unless they claimed them again
+    /// since `last_weekly`. Returns the new balance if they got them.
+    pub async fn claim_weekly(
+        &self,
+        member_id: impl Into<i64>,
+        amount: i64,
+        last_weekly: DateTime<Utc>,
+    ) -> anyhow::Result<Option<i64>> {
+        let member_id = member_id.into();
+        let mut tx = self.pool.begin().await?;
+        let coins = query_scalar!(
+            r#"
+            UPDATE members
+            SET coins = coins + $3, last_weekly = now()
+            WHERE guild_id = $1 AND id = $2 AND last_weekly = $4
+            RETURNING coins
+            "#,
+            self.guild_id,
+            member_id,
+            amount,
+            last_weekly,
+        )
+        .fetch_optional(&mut tx)
+        .await?;
+        if coins.is_some() {
+            self.add_transaction(&mut tx, member_id, amount, "weekly")
+                .await?;
+            tx.commit().await?;
+        }
+        Ok(coins)
+    }
+
+    async fn add_transaction(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Postgres>,
+        member_id: i64,
+        delta: i64,
+        reason: &str,
+    ) -> anyhow::Result<PgQueryResult> {
+        Ok(query!(
+            r#"
+            INSERT INTO transactions (guild_id, member_id, delta, reason)
+            VALUES ($1, $2, $3, $4)
+            "#,
+            self.guild_id,
+            member_id,
+            delta,
+            reason,
+        )
+        .execute(tx)
+        .await?)
+    }
+
+    /// Adds an item to the shop, unless there already is one with the name.
+    pub async fn add_shop_item(
+        &self,
+        name: &str,
+        price: i64,
+        stock: Option<i32>,
+        role_id: Option<i64>,
+        consumable: bool,
+    ) -> anyhow::Result<bool> {
+        Ok(query!(
+            r#"
+            INSERT INTO shop_items (guild_id, name, price, stock, role_id, consumable)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (guild_id, lower(name)) DO NOTHING
+            "#,
+            self.guild_id,
+            name,
+            price,
+            stock,
+            role_id,
+            consumable,
+        )
+        .execute(self.pool)
+        .await?
+        .rows_affected()
+            > 0)
+    }
+
+    /// Takes the item out of the shop and out of every inventory.
+    /// Removes an item from the shop and from every inventory. Returns the
+    /// role that came with it and the members who held it, or `None` if
+    /// there's no item with the name.
+    pub async fn remove_shop_item(&self, name: &str) -> anyhow::Result<Option<RemovedShopItem>> {
+        let mut tx = self.pool.begin().await?;
+        // The inventories go with the item, so they're read first
+        let holders = query_scalar!(
+            r#"
+            SELECT i.member_id
+            FROM inventory_items i
+            JOIN shop_items s ON s.id = i.item_id
+            WHERE s.guild_id = $1 AND lower(s.name) = lower($2) AND i.quantity > 0
+            "#,
+            self.guild_id,
+            name,
+        )
+        .fetch_all(&mut tx)
+        .await?;
+        let role_id = query_scalar!(
+            r#"
+            DELETE FROM shop_items
+            WHERE guild_id = $1 AND lower(name) = lower($2)
+            RETURNING role_id
+            "#,
+            self.guild_id,
+            name,
+        )
+        .fetch_optional(&mut tx)
+        .await?;
+        tx.commit().await?;
+        Ok(role_id.map(|role_id| RemovedShopItem { role_id, holders }))
+    }
+
+    pub async fn get_shop_items(&self) -> anyhow::Result<Vec<ShopItem>> {
+        Ok(query_as!(
+            ShopItem,
+            r#"
+            SELECT id, name, price, stock, role_id, consumable
+            FROM shop_items
+            WHERE guild_id = $1
+            ORDER BY price, name
+            "#,
+            self.guild_id,
+        )
+        .fetch_all(self.pool)
+        .await?)
+    }
+
+    pub async fn get_shop_item(&self, name: &str) -> anyhow::Result<Option<ShopItem>> {
+        Ok(query_as!(
+            ShopItem,
+            r#"
+            SELECT id, name, price, stock, role_id, consumable
+            FROM shop_items
+            WHERE guild_id = $1 AND lower(name) = lower($2)
+            "#,
+            self.guild_id,
+            name,
+        )
+        .fetch_optional(self.pool)
+        .await?)
+    }
+
+    /// Buys one of the item for the member. Returns the member's new
+    /// balance, or `None` if the item sold out or the member can't afford it.
+    pub async fn buy_shop_item(
+        &self,
+        member_id: impl Into<i64>,
+        item: &ShopItem,
+    ) -> anyhow::Result<Option<i64>> {
+        let member_id = member_id.into();
+        let mut tx = self.pool.begin().await?;
+        // Items without stock stay without it
+        let price = query_scalar!(
+            r#"
+            UPDATE shop_items
+            SET stock = stock - 1
+            WHERE id = $1 AND (stock IS NULL OR stock > 0)
+            RETURNING price
+            "#,
+            item.id,
+        )
+        .fetch_optional(&mut tx)
+        .await?;
+        let price = match price {
+            Some(price) => price,
+            None => return Ok(None),
+        };
+        let coins = match self.change_coins(&mut tx, member_id, -price).await? {
+            Some(coins) => coins,
+            None => return Ok(None),
+        };
+        query!(
+            r#"
+            INSERT INTO inventory_items (item_id, member_id, quantity)
+            VALUES ($1, $2, 1)
+            ON CONFLICT (item_id, member_id) DO UPDATE
+            SET quantity = inventory_items.quantity + 1
+            "#,
+            item.id,
+            member_id,
+        )
+        .execute(&mut tx)
+        .await?;
+        self.add_transaction(&mut tx, member_id, -price, &format!("buy {}", item.name))
+            .await?;
+        tx.commit().await?;
+        Ok(Some(coins))
+    }
+
+    /// Sells one of the member's items back to the shop for `refund` coins.
+    /// Returns the member's new balance and how many of the item they have
+    /// left, or `None` if they don't have it.
+    pub async fn sell_shop_item(
+        &self,
+        member_id: impl Into<i64>,
+        item: &ShopItem,
+        refund: i64,
+    ) -> anyhow::Result<Option<(i64, i32)>> {
+        let member_id = member_id.into();
+        let mut tx = self.pool.begin().await?;
+        let quantity = match self
+            .take_inventory_item(&mut tx, member_id, item.id)
+            .await?
+        {
+            Some(quantity) => quantity,
+            None => return Ok(None),
+        };
+        let coins = match self.change_coins(&mut tx, member_id, refund).await? {
+            Some(coins) => coins,
+            None => return Ok(None),
+        };
+        // Items without stock can be bought without limit, so there's
+        // nothing to give back
+        query!(
+            r#"
+            UPDATE shop_items
+            SET stock = stock + 1
+            WHERE id = $1 AND stock IS NOT NULL
+            "#,
+            item.id,
+        )
+        .execute(&mut tx)
+        .await?;
+        self.add_transaction(&mut tx, member_id, refund, &format!("sell {}", item.name))
+            .await?;
+        tx.commit().await?;
+        Ok(Some((coins, quantity)))
+    }
+
+    /// Uses up one of the member's items. Returns how many they have left,
+    /// or `None` if they don't have it.
+    pub async fn use_inventory_item(
+        &self,
+        member_id: impl Into<i64>,
+        item_id: i64,
+    ) -> anyhow::Result<Option<i32>> {
+        let mut tx = self.pool.begin().await?;
+        let quantity = self
+            .take_inventory_item(&mut tx, member_id.into(), item_id)
+            .await?;
+        tx.commit().await?;
+        Ok(quantity)
+    }
+
+    async fn take_inventory_item(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Postgres>,
+        member_id: i64,
+        item_id: i64,
+    ) -> anyhow::Result<Option<i32>> {
+        let quantity = query_scalar!(
+            r#"
+            UPDATE inventory_items
+            SET quantity = quantity - 1
+            WHERE item_id = $1 AND member_id = $2 AND quantity > 0
+            RETURNING quantity
+            "#,
+            item_id,
+            member_id,
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        if quantity == Some(0) {
+            query!(
+                r#"
+                DELETE FROM inventory_items
+                WHERE item_id = $1 AND member_id = $2
+                "#,
+                item_id,
+                member_id,
+            )
+            .execute(tx)
+            .await?;
+        }
+        Ok(quantity)
+    }
+
+    pub async fn get_inventory(
+        &self,
+        member_id: impl Into<i64>,
+    ) -> anyhow::Result<Vec<InventoryItem>> {
+        Ok(query_as!(
+            InventoryItem,
+            r#"
+            SELECT s.name, i.quantity, s.role_id, s.consumable
+            FROM inventory_items i
+            JOIN shop_items s ON s.id = i.item_id
+            WHERE s.guild_id = $1 AND i.member_id = $2
+            ORDER BY s.name
+            "#,
+            self.guild_id,
+            member_id.into(),
+        )
+        .fetch_all(self.pool)
+        .await?)
+    }
+
+    /// The member's latest balance changes, newest first.
+    pub async fn get_transactions(
+        &self,
+        member_id: impl Into<i64>,
+        limit: i64,
+    ) -> anyhow::Result<Vec<Transaction>> {
+        Ok(query_as!(
+            Transaction,
+            r#"
+            SELECT delta, reason, created_at
+            FROM transactions
+            WHERE guild_id = $1 AND member_id = $2
+            ORDER BY id DESC
+            LIMIT $3
+            "#,
+            self.guild_id,
+            member_id.into(),
+            limit,
+        )
+        .fetch_all(self.pool)
+        .await?)
+    }
+
+    pub async fn get_settings(&self) -> anyhow::Result<GuildSettings> {
+        Ok(query_as!(
+            GuildSettings,
+            r#"
+            SELECT playlist_role_id, dj_role_id, vote_skip_percent, idle_timeout_secs, always_on,
+                autoplay, max_user_tracks, max_track_length_secs, max_playlist_size, allow_streams,
+                fair_queue, transfer_tax_percent, daily_amount, daily_cooldown_secs, max_bet
+            FROM guild_settings
+            WHERE guild_id = $1
+            "#,
+            self.guild_id
+        )
+        .fetch_optional(self.pool)
+        .await?
+        .unwrap_or_default())
+    }
+
+    pub async fn set_playlist_role(&self, role_id: Option<i64>) -> anyhow::Result<PgQueryResult> {
+        Ok(query!(
+            r#"
+            INSERT INTO guild_settings (guild_id, playlist_role_id)
+            VALUES ($1, $2)
+            ON CONFLICT (guild_id) DO UPDATE
+            SET playlist_role_id = $2
+            "#,
+            self.guild_id,
+            role_id,
+        )
+        .execute(self.pool)
+        .await?)
+    }
+
+    pub async fn set_dj_role(&self, role_id: Option<i64>) -> anyhow::Result<PgQueryResult> {
+        Ok(query!(
+            r#"
+            INSERT INTO guild_settings (guild_id, dj_role_id)
+            VALUES ($1, $2)
+            ON CONFLICT (guild_id) DO UPDATE
+            SET dj_role_id = $2
+            "#,
+            self.guild_id,
+            role_id,
+        )
+        .execute(self.pool)
+        .await?)
+    }
+
+    pub async fn set_vote_skip_percent(&self, percent: i32) -> anyhow::Result<PgQueryResult> {
+        Ok(query!(
+            r#"
+            INSERT INTO guild_settings (guild_id, vote_skip_percent)
+            VALUES ($1, $2)
+            ON CONFLICT (guild_id) DO UPDATE
+            SET vote_skip_percent = $2
+            "#,
+            self.guild_id,
+            percent,
+        )
+        .execute(self.pool)
+        .await?)
+    }
+
+    pub async fn set_idle_timeout(&self, timeout_secs: i32) -> anyhow::Result<PgQueryResult> {
+        Ok(query!(
+            r#"
+            INSERT INTO guild_settings (guild_id, idle_timeout_secs)
+            VALUES ($1, $2)
+            ON CONFLICT (guild_id) DO UPDATE
+            SET idle_timeout_secs = $2
+            "#,
+            self.guild_id,
+            timeout_secs,
+        )
+        .execute(self.pool)
+        .await?)
+    }
+
+    pub async fn set_always_on(&self, always_on: bool) -> anyhow::Result<PgQueryResult> {
+        Ok(query!(
+            r#"
+            INSERT INTO guild_settings (guild_id, always_on)
+            VALUES ($1, $2)
+            ON CONFLICT (guild_id) DO UPDATE
+            SET always_on = $2
+            "#,
+            self.guild_id,
+            always_on,
+        )
+        .execute(self.pool)
+        .await?)
+    }
+
+    pub async fn set_autoplay(&self, autoplay: bool) -> anyhow::Result<PgQueryResult> {
+        Ok(query!(
+            r#"
+            INSERT INTO guild_settings (guild_id, autoplay)
+            VALUES ($1, $2)
+            ON CONFLICT (guild_id) DO UPDATE
+            SET autoplay = $2
+            "#,
+            self.guild_id,
+            autoplay,
+        )
+        .execute(self.pool)
+        .await?)
+    }
+
+    pub async fn set_fair_queue(&self, fair_queue: bool) -> anyhow::Result<PgQueryResult> {
+        Ok(query!(
+            r#"
+            INSERT INTO guild_settings (guild_id, fair_queue)
+            VALUES ($1, $2)
+            ON CONFLICT (guild_id) DO UPDATE
+            SET fair_queue = $2
+            "#,
+            self.guild_id,
+            fair_queue,
+        )
+        .execute(self.pool)
+        .await?)
+    }
+
+    pub async fn set_transfer_tax(&self, percent: i32) -> anyhow::Result<PgQueryResult> {
+        Ok(query!(
+            r#"
+            INSERT INTO guild_settings (guild_id, transfer_tax_percent)
+            VALUES ($1, $2)
+            ON CONFLICT (guild_id) DO UPDATE
+            SET transfer_tax_percent = $2
+            "#,
+            self.guild_id,
+            percent,
+        )
+        .execute(self.pool)
+        .await?)
+    }
+
+    pub async fn set_daily_reward(
+        &self,
+        amount: i64,
+        cooldown_secs: i32,
+    ) -> anyhow::Result<PgQueryResult> {
+        Ok(query!(
+            r#"
+            INSERT INTO guild_settings (guild_id, daily_amount, daily_cooldown_secs)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (guild_id) DO UPDATE
+            SET daily_amount = $2, daily_cooldown_secs = $3
+            "#,
+            self.guild_id,
+            amount,
+            cooldown_secs,
+        )
+        .execute(self.pool)
+        .await?)
+    }
+
+    pub async fn set_max_bet(&self, max_bet: Option<i64>) -> anyhow::Result<PgQueryResult> {
+        Ok(query!(
+            r#"
+            INSERT INTO guild_settings (guild_id, max_bet)
+            VALUES ($1, $2)
+            ON CONFLICT (guild_id) DO UPDATE
+            SET max_bet = $2
+            "#,
+            self.guild_id,
+            max_bet,
+        )
+        .execute(self.pool)
+        .await?)
+    }
+
+    pub async fn set_music_limits(
+        &self,
+        max_user_tracks: Option<i32>,
+        max_track_length_secs: Option<i32>,
+        max_playlist_size: Option<i32>,
+        allow_streams: bool,
+    ) -> anyhow::Result<PgQueryResult> {
+        Ok(query!(
+            r#"
+            INSERT INTO guild_settings (
+                guild_id, max_user_tracks, max_track_length_secs, max_playlist_size,
+                allow_streams
+            )
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (guild_id) DO UPDATE
+            SET max_user_tracks = $2, max_track_length_secs = $3, max_playlist_size = $4,
+                allow_streams = $5
+            "#,
+            self.guild_id,
+            max_user_tracks,
+            max_track_length_secs,
+            max_playlist_size,
+            allow_streams,
+        )
+        .execute(self.pool)
+        .await?)
+    }
+
+    pub async fn save_music_queue(
+        &self,
+        voice_channel_id: impl Into<i64>,
+        text_channel_id: Option<i64>,
+        position: i64,
+        tracks: &[StoredTrack],
     ) -> anyhow::Result<()> {
-        let query = match last_daily {
-            Some(d) => {
-                query!(
-                    r#"
-                    UPDATE members
-                    SET coins = $3, last_daily = $4
-                    WHERE guild_id = $1 AND id = $2
-                    "#,
-                    self.guild_id,
-                    member_id.into(),
-                    coins,
-                    d,
+        let mut tx = self.pool.begin().await?;
+        query!(
+            r#"
+            INSERT INTO music_queues (guild_id, voice_channel_id, text_channel_id, position)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (guild_id) DO UPDATE
+            SET voice_channel_id = $2, text_channel_id = $3, position = $4
+            "#,
+            self.guild_id,
+            voice_channel_id.into(),
+            text_channel_id,
+            position,
+        )
+        .execute(&mut tx)
+        .await?;
+        query!(
+            r#"
+            DELETE FROM music_queue_tracks
+            WHERE guild_id = $1
+            "#,
+            self.guild_id,
+        )
+        .execute(&mut tx)
+        .await?;
+        for (i, track) in tracks.iter().enumerate() {
+            query!(
+                r#"
+                INSERT INTO music_queue_tracks (
+                    guild_id, queue_position, track, title, author, identifier, uri, length,
+                    is_stream, is_seekable, requester
                 )
-            }
-            None => {
-                query!(
-                    r#"
-                    UPDATE members
-                    SET coins = $3
-                    WHERE guild_id = $1 AND id = $2
-                    "#,
-                    self.guild_id,
-                    member_id.into(),
-                    coins,
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+                "#,
+                self.guild_id,
+                i as i32,
+                track.track,
+                track.title,
+                track.author,
+                track.identifier,
+                track.uri,
+                track.length,
+                track.is_stream,
+                track.is_seekable,
+                track.requester,
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn get_music_queue_tracks(&self) -> anyhow::Result<Vec<StoredTrack>> {
+        Ok(query_as!(
+            StoredTrack,
+            r#"
+            SELECT track, title, author, identifier, uri, length, is_stream, is_seekable, requester
+            FROM music_queue_tracks
+            WHERE guild_id = $1
+            ORDER BY queue_position
+            "#,
+            self.guild_id
+        )
+        .fetch_all(self.pool)
+        .await?)
+    }
+
+    /// Records a track that started playing, only the most recent
+    /// `MAX_HISTORY_LENGTH` tracks are kept.
+    pub async fn add_history_track(&self, track: &StoredTrack) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        query!(
+            r#"
+            INSERT INTO music_history (
+                guild_id, track, title, author, identifier, uri, length, is_stream,
+                is_seekable, requester
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            "#,
+            self.guild_id,
+            track.track,
+            track.title,
+            track.author,
+            track.identifier,
+            track.uri,
+            track.length,
+            track.is_stream,
+            track.is_seekable,
+            track.requester,
+        )
+        .execute(&mut tx)
+        .await?;
+        query!(
+            r#"
+            DELETE FROM music_history
+            WHERE guild_id = $1 AND id NOT IN (
+                SELECT id
+                FROM music_history
+                WHERE guild_id = $1
+                ORDER BY id DESC
+                LIMIT $2
+            )
+            "#,
+            self.guild_id,
+            MAX_HISTORY_LENGTH as i64,
+        )
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// The most recently played tracks, newest first.
+    pub async fn get_history(&self) -> anyhow::Result<Vec<StoredTrack>> {
+        Ok(query_as!(
+            StoredTrack,
+            r#"
+            SELECT track, title, author, identifier, uri, length, is_stream, is_seekable, requester
+            FROM music_history
+            WHERE guild_id = $1
+            ORDER BY id DESC
+            "#,
+            self.guild_id
+        )
+        .fetch_all(self.pool)
+        .await?)
+    }
+
+    /// Records a track that stopped playing, and how much of it was played.
+    pub async fn add_music_play(
+        &self,
+        requester: Option<i64>,
+        uri: &str,
+        title: &str,
+        played_ms: i64,
+    ) -> anyhow::Result<PgQueryResult> {
+        Ok(query!(
+            r#"
+            INSERT INTO music_plays (guild_id, requester, uri, title, played_ms)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+            self.guild_id,
+            requester,
+            uri,
+            title,
+            played_ms,
+        )
+        .execute(self.pool)
+        .await?)
+    }
+
+    /// How many tracks were played since `since` and for how long, by
+    /// everyone or only the ones queued by `requester`.
+    pub async fn get_music_totals(
+        &self,
+        since: DateTime<Utc>,
+        requester: Option<i64>,
+    ) -> anyhow::Result<MusicTotals> {
+        Ok(query_as!(
+            MusicTotals,
+            r#"
+            SELECT COUNT(*) AS "plays!", COALESCE(SUM(played_ms), 0)::bigint AS "played_ms!"
+            FROM music_plays
+            WHERE guild_id = $1 AND played_at >= $2 AND ($3::bigint IS NULL OR requester = $3)
+            "#,
+            self.guild_id,
+            since,
+            requester,
+        )
+        .fetch_one(self.pool)
+        .await?)
+    }
+
+    /// The most played tracks since `since`, by everyone or only the ones
+    /// queued by `requester`.
+    pub async fn get_top_tracks(
+        &self,
+        since: DateTime<Utc>,
+        requester: Option<i64>,
+        limit: i64,
+    ) -> anyhow::Result<Vec<TrackPlays>> {
+        Ok(query_as!(
+            TrackPlays,
+            r#"
+            SELECT MAX(title) AS "title!", uri, COUNT(*) AS "plays!"
+            FROM music_plays
+            WHERE guild_id = $1 AND played_at >= $2 AND ($3::bigint IS NULL OR requester = $3)
+            GROUP BY uri
+            ORDER BY 3 DESC, MAX(played_at) DESC
+            LIMIT $4
+            "#,
+            self.guild_id,
+            since,
+            requester,
+            limit,
+        )
+        .fetch_all(self.pool)
+        .await?)
+    }
+
+    /// The members who queued the most listening time since `since`.
+    pub async fn get_top_listeners(
+        &self,
+        since: DateTime<Utc>,
+        limit: i64,
+    ) -> anyhow::Result<Vec<ListenerPlays>> {
+        Ok(query_as!(
+            ListenerPlays,
+            r#"
+            SELECT requester AS "requester!", COUNT(*) AS "plays!",
+                SUM(played_ms)::bigint AS "played_ms!"
+            FROM music_plays
+            WHERE guild_id = $1 AND played_at >= $2 AND requester IS NOT NULL
+            GROUP BY requester
+            ORDER BY 3 DESC
+            LIMIT $3
+            "#,
+            self.guild_id,
+            since,
+            limit,
+        )
+        .fetch_all(self.pool)
+        .await?)
+    }
+}
+
+impl<'a> Playlists<'a> {
+    pub fn personal(pool: &'a PgPool, user_id: impl Into<i64>) -> Self {
+        Self {
+            pool,
+            user_id: Some(user_id.into()),
+            guild_id: None,
+        }
+    }
+
+    pub fn guild(pool: &'a PgPool, guild_id: impl Into<i64>) -> Self {
+        Self {
+            pool,
+            user_id: None,
+            guild_id: Some(guild_id.into()),
+        }
+    }
+
+    pub async fn list(&self) -> anyhow::Result<Vec<PlaylistSummary>> {
+        Ok(query_as!(
+            PlaylistSummary,
+            r#"
+            SELECT p.name, COUNT(t.playlist_id) AS "track_count!"
+            FROM playlists p
+            LEFT JOIN playlist_tracks t ON t.playlist_id = p.id
+            WHERE p.user_id IS NOT DISTINCT FROM $1 AND p.guild_id IS NOT DISTINCT FROM $2
+            GROUP BY p.id
+            ORDER BY p.name
+            "#,
+            self.user_id,
+            self.guild_id
+        )
+        .fetch_all(self.pool)
+        .await?)
+    }
+
+    pub async fn get_id(&self, name: &str) -> anyhow::Result<Option<i64>> {
+        Ok(query!(
+            r#"
+            SELECT id
+            FROM playlists
+            WHERE user_id IS NOT DISTINCT FROM $1 AND guild_id IS NOT DISTINCT FROM $2
+                AND name = $3
+            "#,
+            self.user_id,
+            self.guild_id,
+            name
+        )
+        .fetch_optional(self.pool)
+        .await?
+        .map(|row| row.id))
+    }
+
+    async fn get_or_create_id(&self, name: &str) -> anyhow::Result<i64> {
+        if let Some(id) = self.get_id(name).await? {
+            return Ok(id);
+        }
+        Ok(query!(
+            r#"
+            INSERT INTO playlists (name, user_id, guild_id)
+            VALUES ($1, $2, $3)
+            RETURNING id
+            "#,
+            name,
+            self.user_id,
+            self.guild_id
+        )
+        .fetch_one(self.pool)
+        .await?
+        .id)
+    }
+
+    /// Appends tracks to a playlist, creating it if needed. With `replace` set,
+    /// the existing tracks of the playlist are dropped first.
+    pub async fn save_tracks(
+        &self,
+        name: &str,
+        tracks: &[StoredTrack],
+        replace: bool,
+    ) -> anyhow::Result<()> {
+        let playlist_id = self.get_or_create_id(name).await?;
+        let mut tx = self.pool.begin().await?;
+        if replace {
+            query!(
+                r#"
+                DELETE FROM playlist_tracks
+                WHERE playlist_id = $1
+                "#,
+                playlist_id
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+        for track in tracks {
+            query!(
+                r#"
+                INSERT INTO playlist_tracks (
+                    playlist_id, playlist_position, track, title, author, identifier, uri,
+                    length, is_stream, is_seekable, requester
                 )
-            }
-        };
-        query.execute(self.pool).await?;
+                SELECT $1, COALESCE(MAX(playlist_position) + 1, 0), $2, $3, $4, $5, $6, $7, $8,
+                    $9, $10
+                FROM playlist_tracks
+                WHERE playlist_id = $1
+                "#,
+                playlist_id,
+                track.track,
+                track.title,
+                track.author,
+                track.identifier,
+                track.uri,
+                track.length,
+                track.is_stream,
+                track.is_seekable,
+                track.requester,
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+        tx.commit().await?;
         Ok(())
     }
+
+    pub async fn get_tracks(&self, name: &str) -> anyhow::Result<Option<Vec<StoredTrack>>> {
+        let playlist_id = match self.get_id(name).await? {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+        Ok(Some(
+            query_as!(
+                StoredTrack,
+                r#"
+                SELECT track, title, author, identifier, uri, length, is_stream, is_seekable,
+                    requester
+                FROM playlist_tracks
+                WHERE playlist_id = $1
+                ORDER BY playlist_position
+                "#,
+                playlist_id
+            )
+            .fetch_all(self.pool)
+            .await?,
+        ))
+    }
+
+    pub async fn rename(&self, name: &str, new_name: &str) -> anyhow::Result<bool> {
+        let result = query!(
+            r#"
+            UPDATE playlists
+            SET name = $4
+            WHERE user_id IS NOT DISTINCT FROM $1 AND guild_id IS NOT DISTINCT FROM $2
+                AND name = $3
+            "#,
+            self.user_id,
+            self.guild_id,
+            name,
+            new_name
+        )
+        .execute(self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn delete(&self, name: &str) -> anyhow::Result<Option<i64>> {
+        Ok(query!(
+            r#"
+            DELETE FROM playlists
+            WHERE user_id IS NOT DISTINCT FROM $1 AND guild_id IS NOT DISTINCT FROM $2
+                AND name = $3
+            RETURNING id
+            "#,
+            self.user_id,
+            self.guild_id,
+            name
+        )
+        .fetch_optional(self.pool)
+        .await?
+        .map(|row| row.id))
+    }
+}
+
+pub async fn get_music_queues(pool: &PgPool) -> anyhow::Result<Vec<MusicQueue>> {
+    Ok(query_as!(
+        MusicQueue,
+        r#"
+        SELECT guild_id, voice_channel_id, text_channel_id, position
+        FROM music_queues
+        "#
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Drops the saved queues of every guild that isn't in `active_guild_ids`.
+pub async fn delete_stale_music_queues(
+    pool: &PgPool,
+    active_guild_ids: &[i64],
+) -> anyhow::Result<PgQueryResult> {
+    Ok(query!(
+        r#"
+        DELETE FROM music_queues
+        WHERE NOT (guild_id = ANY($1))
+        "#,
+        active_guild_ids
+    )
+    .execute(pool)
+    .await?)
 }
 
 pub async fn get_all_guild_ids(pool: &PgPool) -> anyhow::Result<Vec<i64>> {
@@ -8,11 +8,12 @@ use poise::{EditTracker, Event, Framework, FrameworkBuilder, FrameworkOptions, P
 use tracing::{error, info};
 
 use crate::{
-    commands::{economy, fun, meta, music, reddit, roleplay},
+    commands::{casino, economy, fun, meta, music, playlists, reddit, roleplay, shop},
     constants::PREFIX,
     data::{Data, PgPoolContainer},
     database::Guild,
-    services::start_services,
+    lavalink::check_voice_listeners,
+    services::{start_queue_persistence, start_services},
     types::{Error, SerenityContext},
 };
 
@@ -31,11 +32,15 @@ pub async fn listener<'a>(
             let ctx = Arc::new(ctx.clone());
             if *(crate::constants::ENABLE_SERVICES) {
                 if !data.is_services_running.load(Ordering::Relaxed) {
-                    start_services(ctx).await;
+                    start_services(Arc::clone(&ctx)).await;
                     data.is_services_running.swap(true, Ordering::Relaxed);
                     info!("Services started");
                 }
             }
+            if !data.is_music_restored.swap(true, Ordering::Relaxed) {
+                start_queue_persistence(ctx, data.songbird.clone(), data.lavalink.clone()).await;
+                info!("Music queue persistence and node failover started");
+            }
         }
         Event::GuildCreate { guild, is_new: _ } => {
             let guild_id = guild.id;
@@ -84,6 +89,14 @@ pub async fn listener<'a>(
                 }
             }
         }
+        Event::VoiceStateUpdate { old: _, new } => {
+            if let Some(guild_id) = new.guild_id {
+                let lavalink = data.lavalink.client(guild_id.0).await;
+                if let Err(why) = check_voice_listeners(ctx, &lavalink, guild_id).await {
+                    error!("error checking voice listeners {:?}", why);
+                }
+            }
+        }
         _ => return Ok(()),
     }
     Ok(())
@@ -119,18 +132,72 @@ pub fn get_framework_builder(
         .command(music::join(), |f| f.category("Music"))
         .command(music::leave(), |f| f.category("Music"))
         .command(music::play(), |f| f.category("Music"))
+        .command(music::play_next(), |f| f.category("Music"))
+        .command(music::search(), |f| f.category("Music"))
         .command(music::skip(), |f| f.category("Music"))
         .command(music::pause(), |f| f.category("Music"))
         .command(music::resume(), |f| f.category("Music"))
         .command(music::seek(), |f| f.category("Music"))
+        .command(music::forward(), |f| f.category("Music"))
+        .command(music::rewind(), |f| f.category("Music"))
         .command(music::clear(), |f| f.category("Music"))
         .command(music::now_playing(), |f| f.category("Music"))
         .command(music::queue(), |f| f.category("Music"))
+        .command(music::queue_export(), |f| f.category("Music"))
+        .command(music::history(), |f| f.category("Music"))
+        .command(music::previous(), |f| f.category("Music"))
+        .command(music::music_stats(), |f| f.category("Music"))
+        .command(music::loop_mode(), |f| f.category("Music"))
+        .command(music::shuffle(), |f| f.category("Music"))
+        .command(music::remove(), |f| f.category("Music"))
+        .command(music::move_track(), |f| f.category("Music"))
+        .command(music::skip_to(), |f| f.category("Music"))
+        .command(music::volume(), |f| f.category("Music"))
+        .command(music::eq(), |f| f.category("Music"))
+        .command(music::bassboost(), |f| f.category("Music"))
+        .command(music::nightcore(), |f| f.category("Music"))
+        .command(music::eight_d(), |f| f.category("Music"))
+        .command(music::filters(), |f| f.category("Music"))
+        .command(music::dj_role(), |f| f.category("Music"))
+        .command(music::vote_skip_percent(), |f| f.category("Music"))
+        .command(music::idle_timeout(), |f| f.category("Music"))
+        .command(music::always_on(), |f| f.category("Music"))
+        .command(music::autoplay(), |f| f.category("Music"))
+        .command(music::music_limits(), |f| f.category("Music"))
+        .command(music::fair_queue(), |f| f.category("Music"))
+        .command(music::lavalink(), |f| f.category("Music"))
+        // Playlists
+        .command(playlists::playlists(), |f| f.category("Playlists"))
+        .command(playlists::playlist_save(), |f| f.category("Playlists"))
+        .command(playlists::playlist_add(), |f| f.category("Playlists"))
+        .command(playlists::playlist_export(), |f| f.category("Playlists"))
+        .command(playlists::playlist_rename(), |f| f.category("Playlists"))
+        .command(playlists::playlist_delete(), |f| f.category("Playlists"))
+        .command(playlists::playlist_role(), |f| f.category("Playlists"))
         // Economy
         .command(economy::balance(), |f| f.category("Economy"))
         .command(economy::daily(), |f| f.category("Economy"))
+        .command(economy::weekly(), |f| f.category("Economy"))
         .command(economy::gamble(), |f| f.category("Economy"))
         .command(economy::leaderboard(), |f| f.category("Economy"))
+        .command(economy::transactions(), |f| f.category("Economy"))
+        .command(economy::pay(), |f| f.category("Economy"))
+        .command(economy::transfer_tax(), |f| f.category("Economy"))
+        .command(economy::daily_reward(), |f| f.category("Economy"))
+        // Casino
+        .command(casino::blackjack(), |f| f.category("Casino"))
+        .command(casino::slots(), |f| f.category("Casino"))
+        .command(casino::coinflip(), |f| f.category("Casino"))
+        .command(casino::roulette(), |f| f.category("Casino"))
+        .command(casino::max_bet(), |f| f.category("Casino"))
+        // Shop
+        .command(shop::shop(), |f| f.category("Shop"))
+        .command(shop::shop_add(), |f| f.category("Shop"))
+        .command(shop::shop_remove(), |f| f.category("Shop"))
+        .command(shop::buy(), |f| f.category("Shop"))
+        .command(shop::sell(), |f| f.category("Shop"))
+        .command(shop::use_item(), |f| f.category("Shop"))
+        .command(shop::inventory(), |f| f.category("Shop"))
         // Fun
         .command(fun::chuck(), |f| f.category("Fun"))
         .command(fun::dice(), |f| f.category("Fun"))
@@ -1,43 +1,1132 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
 
-use lavalink_rs::gateway::LavalinkEventHandler;
+use lavalink_rs::{
+    error::LavalinkResult,
+    gateway::LavalinkEventHandler,
+    model::{Band, Filters, Rotation, Stats, TimeScale, Track, TrackQueue},
+    LavalinkClient,
+};
 use poise::{
-    serenity::async_trait,
-    serenity_prelude::{Channel, GuildId, Http, Mentionable, UserId},
+    serenity::{async_trait, builder::CreateEmbed},
+    serenity_prelude::{
+        ChannelId, Guild, GuildId, Http, Mentionable, MessageId, RwLock, TypeMap, UserId,
+    },
 };
+use rand::seq::SliceRandom;
 use songbird::Songbird;
-use tracing::{debug, info};
+use sqlx::PgPool;
+use strum_macros::{EnumString, ToString};
+use tokio::time::Instant;
+use tracing::{debug, error, info};
 
 use crate::{
-    constants::MAX_SINGLE_ENTRY_LENGTH,
-    types::{IdleHashMap, LastMessageHashMap},
-    utils::helpers::{chop_str, display_time_span},
+    constants::{LIVE_INDICATOR, MAX_HISTORY_LENGTH, MAX_SINGLE_ENTRY_LENGTH},
+    data::{
+        AlonePausedGuilds, CurrentTrackMap, FiltersMap, IdleGuildMap, LoopModeMap,
+        NowPlayingMessageMap, PlayerPositionMap, SkipVotesMap,
+    },
+    database::{self, StoredTrack},
+    types::{
+        CurrentTrackHashMap, Error, FiltersHashMap, GuildNodeHashMap, HistoryHashMap, IdleHashMap,
+        LastMessageHashMap, LoopModeHashMap, NodeStatsHashMap, NowPlayingMessageHashMap,
+        PlayerPositionHashMap, SerenityContext, SkipVotesHashMap,
+    },
+    utils::helpers::{chop_str, display_time_span, progress_bar},
 };
 
+/// What happens to a track once it finishes playing.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, EnumString, ToString)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub enum LoopMode {
+    Off,
+    Track,
+    Queue,
+}
+
+impl Default for LoopMode {
+    fn default() -> Self {
+        LoopMode::Off
+    }
+}
+
+/// Equalizer presets, each one sets the gain of all 15 Lavalink bands.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, EnumString, ToString)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub enum EqPreset {
+    Bassboost,
+    Pop,
+    Rock,
+    Soft,
+    Treble,
+}
+
+impl EqPreset {
+    fn gains(self) -> [f64; 15] {
+        match self {
+            EqPreset::Bassboost => [
+                0.25, 0.2, 0.15, 0.1, 0.05, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0,
+            ],
+            EqPreset::Pop => [
+                -0.02, -0.01, 0.08, 0.1, 0.15, 0.1, 0.03, -0.02, -0.035, -0.05, -0.05, -0.05,
+                -0.05, -0.05, -0.05,
+            ],
+            EqPreset::Rock => [
+                0.3, 0.25, 0.2, 0.1, 0.05, -0.05, -0.15, -0.2, -0.1, -0.05, 0.05, 0.1, 0.2, 0.25,
+                0.3,
+            ],
+            EqPreset::Soft => [
+                0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, -0.25, -0.25, -0.25, -0.25, -0.25, -0.25,
+                -0.25,
+            ],
+            EqPreset::Treble => [
+                -0.2, -0.2, -0.15, -0.1, 0.0, 0.0, 0.0, 0.0, 0.1, 0.15, 0.2, 0.25, 0.25, 0.25, 0.25,
+            ],
+        }
+    }
+}
+
+/// Audio filters that are active in a guild.
+#[derive(Clone, Debug, Default)]
+pub struct AudioFilters {
+    /// Volume in percent, `None` means 100%.
+    pub volume: Option<u16>,
+    pub equalizer: Option<EqPreset>,
+    pub nightcore: bool,
+    pub rotation: bool,
+}
+
+impl AudioFilters {
+    /// Lists the active filters in a human readable way.
+    pub fn describe(&self) -> Option<String> {
+        let mut active = Vec::new();
+        if let Some(volume) = self.volume {
+            active.push(format!("Volume {}%", volume));
+        }
+        if let Some(preset) = self.equalizer {
+            active.push(format!("EQ {}", preset.to_string()));
+        }
+        if self.nightcore {
+            active.push("Nightcore".to_owned());
+        }
+        if self.rotation {
+            active.push("8D".to_owned());
+        }
+        if active.is_empty() {
+            None
+        } else {
+            Some(active.join(", "))
+        }
+    }
+
+    fn to_lavalink(&self) -> Filters {
+        Filters {
+            volume: self.volume.map(|volume| volume as f64 / 100.0),
+            equalizer: self.equalizer.map(|preset| {
+                preset
+                    .gains()
+                    .iter()
+                    .enumerate()
+                    .map(|(band, gain)| Band {
+                        band: band as u8,
+                        gain: *gain,
+                    })
+                    .collect()
+            }),
+            timescale: if self.nightcore {
+                Some(TimeScale {
+                    speed: Some(1.2),
+                    pitch: Some(1.2),
+                    rate: Some(1.0),
+                })
+            } else {
+                None
+            },
+            rotation: if self.rotation {
+                Some(Rotation { rotation_hz: 0.2 })
+            } else {
+                None
+            },
+            ..Default::default()
+        }
+    }
+}
+
+/// Sends the filters of a guild to its Lavalink player.
+pub async fn apply_filters(
+    client: &LavalinkClient,
+    guild_id: u64,
+    filters: &AudioFilters,
+) -> LavalinkResult<()> {
+    client.set_filters(guild_id, filters.to_lavalink()).await
+}
+
+/// The message of a guild that shows what's playing, kept up to date while
+/// tracks play.
+pub struct NowPlayingMessage {
+    pub channel_id: ChannelId,
+    pub message_id: MessageId,
+    pub last_edit: Instant,
+}
+
+const NOW_PLAYING_UPDATE_INTERVAL: Duration = Duration::from_secs(10);
+const PROGRESS_BAR_WIDTH: usize = 20;
+
+/// Works out the current playback position in milliseconds from the last
+/// position Lavalink reported.
+pub fn estimate_position(report: Option<&(u64, Instant)>, is_paused: bool) -> u64 {
+    match report {
+        Some((position, _)) if is_paused => *position,
+        Some((position, reported)) => position + reported.elapsed().as_millis() as u64,
+        None => 0,
+    }
+}
+
+pub fn now_playing_embed<'a>(
+    e: &'a mut CreateEmbed,
+    now_playing: &TrackQueue,
+    position: u64,
+    filters: Option<&str>,
+) -> &'a mut CreateEmbed {
+    let track_info = now_playing.track.info.as_ref().unwrap();
+    let progress = if track_info.is_stream {
+        format!("{} {}", LIVE_INDICATOR, display_time_span(position))
+    } else {
+        let position = position.min(track_info.length);
+        format!(
+            "`{}`\n{} / {}",
+            progress_bar(position, track_info.length, PROGRESS_BAR_WIDTH),
+            display_time_span(position),
+            display_time_span(track_info.length)
+        )
+    };
+
+    e.title("Now Playing")
+        .field(
+            "Track:",
+            format!(
+                "[{}]({})",
+                chop_str(track_info.title.as_str(), MAX_SINGLE_ENTRY_LENGTH),
+                track_info.uri,
+            ),
+            false,
+        )
+        .field("Progress:", progress, false)
+        .field(
+            "Requested By:",
+            // Restored and stored tracks might not know who requested them
+            now_playing.requester.map_or_else(
+                || "Unknown".to_owned(),
+                |requester| UserId(requester.0).mention().to_string(),
+            ),
+            true,
+        );
+    if let Some(filters) = filters {
+        e.field("Filters:", filters, true);
+    }
+    e
+}
+
+/// How much work a node is doing, lower is better. Follows the penalties
+/// Lavalink suggests for balancing players between nodes.
+pub fn node_penalty(stats: &Stats) -> f64 {
+    let cpu_penalty = 1.05_f64.powf(100.0 * stats.cpu.system_load) * 10.0 - 10.0;
+    let frame_penalty = match &stats.frame_stats {
+        // Frames are reported per minute, these are the ones that went missing
+        Some(frame_stats) => {
+            let deficit = frame_stats.deficit as f64 / 3000.0;
+            let nulled = frame_stats.nulled as f64 / 3000.0;
+            1.03_f64.powf(500.0 * deficit) * 600.0 - 600.0
+                + (1.03_f64.powf(500.0 * nulled) * 300.0 - 300.0) * 2.0
+        }
+        None => 0.0,
+    };
+    stats.playing_players as f64 + cpu_penalty + frame_penalty
+}
+
+/// The Lavalink nodes the bot is connected to. A guild's player stays on the
+/// node it was created on, new players go to the least loaded node.
+#[derive(Clone)]
+pub struct LavalinkNodes {
+    clients: Vec<LavalinkClient>,
+    node_stats: NodeStatsHashMap,
+    guild_node_map: GuildNodeHashMap,
+}
+
+impl LavalinkNodes {
+    pub fn new(
+        clients: Vec<LavalinkClient>,
+        node_stats: NodeStatsHashMap,
+        guild_node_map: GuildNodeHashMap,
+    ) -> Self {
+        Self {
+            clients,
+            node_stats,
+            guild_node_map,
+        }
+    }
+
+    pub fn clients(&self) -> &[LavalinkClient] {
+        &self.clients
+    }
+
+    /// Whether the node reported its stats recently. Lavalink sends them
+    /// every minute, a node that stops doing so is considered gone.
+    pub async fn is_healthy(&self, node: usize) -> bool {
+        self.node_stats
+            .read()
+            .await
+            .get(&node)
+            .map_or(false, |(_, reported)| {
+                reported.elapsed() < NODE_STATS_TIMEOUT
+            })
+    }
+
+    /// The healthy node with the lowest penalty, leaving out `exclude`.
+    pub async fn least_loaded(&self, exclude: Option<usize>) -> Option<usize> {
+        let node_stats = self.node_stats.read().await;
+        (0..self.clients.len())
+            .filter(|node| Some(*node) != exclude)
+            .filter_map(|node| {
+                let (stats, reported) = node_stats.get(&node)?;
+                if reported.elapsed() < NODE_STATS_TIMEOUT {
+                    Some((node, node_penalty(stats)))
+                } else {
+                    None
+                }
+            })
+            .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+            .map(|(node, _)| node)
+    }
+
+    /// The last stats every node reported and when it reported them.
+    pub async fn stats(&self) -> Vec<Option<(Stats, Instant)>> {
+        let node_stats = self.node_stats.read().await;
+        (0..self.clients.len())
+            .map(|node| node_stats.get(&node).cloned())
+            .collect()
+    }
+
+    /// The node the guild's player is on, if it has one.
+    pub async fn node_of(&self, guild_id: u64) -> Option<usize> {
+        self.guild_node_map.read().await.get(&guild_id).copied()
+    }
+
+    /// Guilds whose player is on the node.
+    pub async fn guilds_on(&self, node: usize) -> Vec<u64> {
+        self.guild_node_map
+            .read()
+            .await
+            .iter()
+            .filter(|(_, guild_node)| **guild_node == node)
+            .map(|(guild_id, _)| *guild_id)
+            .collect()
+    }
+
+    /// The client of the node the guild's player is on, or of the node a new
+    /// player would be created on.
+    pub async fn client(&self, guild_id: u64) -> LavalinkClient {
+        let node = match self.node_of(guild_id).await {
+            Some(node) => node,
+            None => self.least_loaded(None).await.unwrap_or_default(),
+        };
+        self.clients[node].clone()
+    }
+
+    /// Puts the guild's player on the least loaded node, unless it already
+    /// is on one.
+    pub async fn assign(&self, guild_id: u64) -> LavalinkClient {
+        let node = match self.node_of(guild_id).await {
+            Some(node) => node,
+            None => {
+                let node = self.least_loaded(None).await.unwrap_or_default();
+                self.guild_node_map.write().await.insert(guild_id, node);
+                node
+            }
+        };
+        self.clients[node].clone()
+    }
+
+    /// Destroys the guild's player, so the next one can go to another node.
+    pub async fn destroy(&self, guild_id: u64) -> LavalinkResult<()> {
+        let node = self.guild_node_map.write().await.remove(&guild_id);
+        self.clients[node.unwrap_or_default()]
+            .destroy(guild_id)
+            .await
+    }
+}
+
 pub struct LavalinkHandler {
+    node: usize,
     guild_last_message_map: LastMessageHashMap,
     guild_idle_map: IdleHashMap,
+    guild_loop_mode_map: LoopModeHashMap,
+    guild_player_position_map: PlayerPositionHashMap,
+    guild_filters_map: FiltersHashMap,
+    guild_now_playing_message_map: NowPlayingMessageHashMap,
+    guild_skip_votes_map: SkipVotesHashMap,
+    guild_history_map: HistoryHashMap,
+    // Lavalink drops finished tracks from the queue before `track_finish` is
+    // called, so the playing track is kept around for looping.
+    guild_current_track_map: CurrentTrackHashMap,
+    node_stats: NodeStatsHashMap,
+    guild_node_map: GuildNodeHashMap,
+    // The track that last failed to play, and how many times it was retried
+    guild_track_retries_map: RwLock<HashMap<u64, (String, u8)>>,
+    // Why the playing track failed, until `track_finish` deals with it
+    guild_track_failures_map: RwLock<HashMap<u64, String>>,
+    pool: PgPool,
     http: Arc<Http>,
     songbird: Arc<Songbird>,
+    data: Arc<RwLock<TypeMap>>,
 }
 
 impl LavalinkHandler {
     pub fn new(
+        node: usize,
         guild_last_message_map: LastMessageHashMap,
         guild_idle_map: IdleHashMap,
+        guild_loop_mode_map: LoopModeHashMap,
+        guild_player_position_map: PlayerPositionHashMap,
+        guild_filters_map: FiltersHashMap,
+        guild_now_playing_message_map: NowPlayingMessageHashMap,
+        guild_skip_votes_map: SkipVotesHashMap,
+        guild_history_map: HistoryHashMap,
+        guild_current_track_map: CurrentTrackHashMap,
+        node_stats: NodeStatsHashMap,
+        guild_node_map: GuildNodeHashMap,
+        pool: PgPool,
         http: Arc<Http>,
         songbird: Arc<Songbird>,
+        data: Arc<RwLock<TypeMap>>,
     ) -> Self {
         Self {
+            node,
             guild_last_message_map,
             guild_idle_map,
+            guild_loop_mode_map,
+            guild_player_position_map,
+            guild_filters_map,
+            guild_now_playing_message_map,
+            guild_skip_votes_map,
+            guild_history_map,
+            guild_current_track_map,
+            node_stats,
+            guild_node_map,
+            guild_track_retries_map: RwLock::new(HashMap::new()),
+            guild_track_failures_map: RwLock::new(HashMap::new()),
+            pool,
             http,
             songbird,
+            data,
+        }
+    }
+
+    /// Shows the playing track in the guild's now playing message. With
+    /// `force` unset, the message is only edited every so often and no new
+    /// message is sent.
+    async fn update_now_playing(&self, client: &LavalinkClient, guild_id: u64, force: bool) {
+        let now_playing = client
+            .nodes()
+            .await
+            .get(&guild_id)
+            .and_then(|node| Some((node.now_playing.clone()?, node.is_paused)));
+        let (now_playing, is_paused) = match now_playing {
+            Some(now_playing) => now_playing,
+            None => return,
+        };
+
+        let position = {
+            let player_position_map = self.guild_player_position_map.read().await;
+            estimate_position(player_position_map.get(&guild_id), is_paused)
+        };
+        let filters = self
+            .guild_filters_map
+            .read()
+            .await
+            .get(&guild_id)
+            .and_then(|filters| filters.describe());
+
+        let tracked = self
+            .guild_now_playing_message_map
+            .read()
+            .await
+            .get(&guild_id)
+            .map(|message| (message.channel_id, message.message_id, message.last_edit));
+        match tracked {
+            Some((channel_id, message_id, last_edit)) => {
+                if !force && last_edit.elapsed() < NOW_PLAYING_UPDATE_INTERVAL {
+                    return;
+                }
+                let result = channel_id
+                    .edit_message(&self.http, message_id, |m| {
+                        m.embed(|e| {
+                            now_playing_embed(e, &now_playing, position, filters.as_deref())
+                        })
+                    })
+                    .await;
+                let mut now_playing_message_map = self.guild_now_playing_message_map.write().await;
+                match result {
+                    Ok(_) => {
+                        if let Some(message) = now_playing_message_map.get_mut(&guild_id) {
+                            message.last_edit = Instant::now();
+                        }
+                    }
+                    // Most likely someone deleted the message, a new one is
+                    // sent with the next track
+                    Err(_) => {
+                        now_playing_message_map.remove(&guild_id);
+                    }
+                }
+            }
+            None if force => {
+                let channel_id = match self.guild_last_message_map.read().await.get(&guild_id) {
+                    Some(channel_id) => *channel_id,
+                    None => return,
+                };
+                if let Ok(message) = channel_id
+                    .send_message(&self.http, |m| {
+                        m.embed(|e| {
+                            now_playing_embed(e, &now_playing, position, filters.as_deref())
+                        })
+                    })
+                    .await
+                {
+                    let mut now_playing_message_map =
+                        self.guild_now_playing_message_map.write().await;
+                    now_playing_message_map.insert(
+                        guild_id,
+                        NowPlayingMessage {
+                            channel_id,
+                            message_id: message.id,
+                            last_edit: Instant::now(),
+                        },
+                    );
+                }
+            }
+            None => {}
+        }
+    }
+
+    // Marks the guild's now playing message as done, it isn't updated anymore
+    // after this.
+    async fn finish_now_playing(&self, guild_id: u64) {
+        let message = self
+            .guild_now_playing_message_map
+            .write()
+            .await
+            .remove(&guild_id);
+        if let Some(message) = message {
+            let _ = message
+                .channel_id
+                .edit_message(&self.http, message.message_id, |m| {
+                    m.embed(|e| {
+                        e.title("Queue Finished")
+                            .description("Nothing is left in the queue, add more with `play`.")
+                    })
+                })
+                .await;
+        }
+    }
+
+    /// Remembers a track that started playing. Repeats of the same track, like
+    /// when it's looped, are only recorded once.
+    async fn record_history(&self, guild_id: u64, track: &TrackQueue) {
+        if let Err(why) = load_history(&self.guild_history_map, &self.pool, guild_id).await {
+            error!("error loading music history {:?}", why);
+        }
+        {
+            let mut history_map = self.guild_history_map.write().await;
+            let history = history_map.entry(guild_id).or_default();
+            if history
+                .front()
+                .map_or(false, |last| last.track.track == track.track.track)
+            {
+                return;
+            }
+            history.push_front(track.clone());
+            history.truncate(MAX_HISTORY_LENGTH);
+        }
+
+        if let Err(why) = database::Guild::new(&self.pool, guild_id as i64)
+            .add_history_track(&StoredTrack::from(track))
+            .await
+        {
+            error!("error saving track to music history {:?}", why);
         }
     }
+
+    /// Adds a track that stopped playing to the music stats, with how much of
+    /// it was played.
+    async fn record_play(
+        &self,
+        client: &LavalinkClient,
+        guild_id: u64,
+        track: &TrackQueue,
+        finished: bool,
+    ) {
+        let track_info = match track.track.info.as_ref() {
+            Some(track_info) => track_info,
+            None => return,
+        };
+        let played_ms = if finished && !track_info.is_stream {
+            track_info.length
+        } else {
+            let is_paused = client
+                .nodes()
+                .await
+                .get(&guild_id)
+                .map_or(false, |node| node.is_paused);
+            let player_position_map = self.guild_player_position_map.read().await;
+            estimate_position(player_position_map.get(&guild_id), is_paused).min(track_info.length)
+        };
+        if played_ms == 0 {
+            return;
+        }
+
+        if let Err(why) = database::Guild::new(&self.pool, guild_id as i64)
+            .add_music_play(
+                track.requester.map(|requester| requester.0 as i64),
+                &track_info.uri,
+                &track_info.title,
+                played_ms as i64,
+            )
+            .await
+        {
+            error!("error saving music play {:?}", why);
+        }
+    }
+
+    async fn autoplay_enabled(&self, guild_id: u64) -> bool {
+        match database::Guild::new(&self.pool, guild_id as i64)
+            .get_settings()
+            .await
+        {
+            Ok(settings) => settings.autoplay,
+            Err(why) => {
+                error!("error getting guild settings {:?}", why);
+                false
+            }
+        }
+    }
+
+    /// Picks a track to keep the music going, either another track by the
+    /// author of the last one or something from the guild's history. Recently
+    /// played tracks are avoided.
+    async fn find_autoplay_track(
+        &self,
+        client: &LavalinkClient,
+        guild_id: u64,
+    ) -> Option<TrackQueue> {
+        let last_track = self
+            .guild_current_track_map
+            .read()
+            .await
+            .get(&guild_id)
+            .cloned()?;
+        let last_info = last_track.track.info.as_ref()?;
+        let history: Vec<Track> = self
+            .guild_history_map
+            .read()
+            .await
+            .get(&guild_id)
+            .map(|history| history.iter().map(|played| played.track.clone()).collect())
+            .unwrap_or_default();
+
+        let recent: HashSet<&str> = history
+            .iter()
+            .take(AUTOPLAY_RECENT_TRACKS)
+            .filter_map(|track| track.info.as_ref())
+            .map(|info| info.identifier.as_str())
+            .chain(std::iter::once(last_info.identifier.as_str()))
+            .collect();
+        let is_candidate = |track: &Track| {
+            track.info.as_ref().map_or(false, |info| {
+                !info.is_stream && !recent.contains(info.identifier.as_str())
+            })
+        };
+
+        let mut candidates: Vec<Track> = match client.search_tracks(&last_info.author).await {
+            Ok(results) => results.tracks.into_iter().filter(is_candidate).collect(),
+            Err(e) => {
+                error!("Failed to search for an autoplay track: {}", e);
+                Vec::new()
+            }
+        };
+        if candidates.is_empty() {
+            candidates = history
+                .iter()
+                .filter(|&track| is_candidate(track))
+                .cloned()
+                .collect();
+        }
+
+        let track = candidates.choose(&mut rand::thread_rng())?.clone();
+        Some(TrackQueue {
+            track,
+            start_time: 0,
+            end_time: None,
+            requester: last_track.requester,
+        })
+    }
+
+    /// Queues a track that failed again, to play from where it stopped. Once
+    /// it failed too often it's left out and the guild is told about it.
+    /// Lavalink ends failed tracks on its own, so the queue moves on the same
+    /// way as for any other track. Returns whether the track is retried.
+    async fn handle_failed_track(
+        &self,
+        client: &LavalinkClient,
+        guild_id: u64,
+        track: TrackQueue,
+        error: &str,
+    ) -> bool {
+        let attempts = {
+            let mut track_retries_map = self.guild_track_retries_map.write().await;
+            let retries = track_retries_map
+                .entry(guild_id)
+                .or_insert_with(|| (track.track.track.clone(), 0));
+            if retries.0 != track.track.track {
+                *retries = (track.track.track.clone(), 0);
+            }
+            retries.1 += 1;
+            retries.1
+        };
+        if attempts <= MAX_TRACK_RETRIES {
+            let position = self
+                .guild_player_position_map
+                .read()
+                .await
+                .get(&guild_id)
+                .map_or(0, |(position, _)| *position);
+            info!(
+                "Retrying track in guild {} ({}/{}): {}",
+                guild_id, attempts, MAX_TRACK_RETRIES, error
+            );
+            let retry = TrackQueue {
+                start_time: position,
+                ..track.clone()
+            };
+            match enqueue_front(client, guild_id, retry).await {
+                Ok(_) => return true,
+                Err(e) => error!("Failed to retry track: {}", e),
+            }
+        }
+
+        {
+            let mut track_retries_map = self.guild_track_retries_map.write().await;
+            track_retries_map.remove(&guild_id);
+        }
+
+        let channel_id = match self.guild_last_message_map.read().await.get(&guild_id) {
+            Some(channel_id) => *channel_id,
+            None => return false,
+        };
+        let track_info = track.track.info.as_ref().unwrap();
+        let _ = channel_id
+            .send_message(&self.http, |m| {
+                m.embed(|e| {
+                    e.title("Skipped Track").description(format!(
+                        "Couldn't play [{}]({}): {}",
+                        chop_str(track_info.title.as_str(), MAX_SINGLE_ENTRY_LENGTH),
+                        track_info.uri,
+                        error
+                    ))
+                })
+            })
+            .await;
+        false
+    }
 }
 
-const MAX_IDLE: Duration = Duration::from_secs(900);
+pub async fn join_internal<G, C>(
+    ctx: &SerenityContext,
+    songbird: &Songbird,
+    lavalink: &LavalinkNodes,
+    guild_id: G,
+    channel_id: C,
+) -> Result<(), Error>
+where
+    G: Into<u64>,
+    C: Into<u64>,
+{
+    let guild_id: u64 = guild_id.into();
+    let client = lavalink.assign(guild_id).await;
+    connect_voice(songbird, &client, guild_id, channel_id.into()).await?;
+
+    {
+        let data = ctx.data.read().await;
+        let mut idle_hash_map = data
+            .get::<IdleGuildMap>()
+            .expect("Expected IdleGuildMap in TypeMap")
+            .write()
+            .await;
+        idle_hash_map.insert(guild_id, Instant::now());
+        let mut alone_paused_guilds = data
+            .get::<AlonePausedGuilds>()
+            .expect("Expected AlonePausedGuilds in TypeMap")
+            .write()
+            .await;
+        alone_paused_guilds.remove(&guild_id);
+    }
+
+    Ok(())
+}
+
+/// Joins the voice channel through the gateway and hands the voice session
+/// over to Lavalink.
+async fn connect_voice(
+    songbird: &Songbird,
+    lavalink: &LavalinkClient,
+    guild_id: u64,
+    channel_id: u64,
+) -> Result<(), Error> {
+    let (_, handler) = songbird.join_gateway(guild_id, channel_id).await;
+
+    match handler {
+        Ok(connection_info) => match lavalink
+            .create_session_with_songbird(&connection_info)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Box::new(e)),
+        },
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+/// Forgets everything kept about the guild's player once it's destroyed, so
+/// the next one doesn't inherit its filters, loop mode or messages.
+pub async fn clear_player_state(data: &TypeMap, guild_id: u64) {
+    data.get::<IdleGuildMap>()
+        .expect("Expected IdleGuildMap in TypeMap")
+        .write()
+        .await
+        .remove(&guild_id);
+    data.get::<AlonePausedGuilds>()
+        .expect("Expected AlonePausedGuilds in TypeMap")
+        .write()
+        .await
+        .remove(&guild_id);
+    data.get::<NowPlayingMessageMap>()
+        .expect("Expected NowPlayingMessageMap in TypeMap")
+        .write()
+        .await
+        .remove(&guild_id);
+    data.get::<FiltersMap>()
+        .expect("Expected FiltersMap in TypeMap")
+        .write()
+        .await
+        .remove(&guild_id);
+    data.get::<LoopModeMap>()
+        .expect("Expected LoopModeMap in TypeMap")
+        .write()
+        .await
+        .remove(&guild_id);
+    data.get::<PlayerPositionMap>()
+        .expect("Expected PlayerPositionMap in TypeMap")
+        .write()
+        .await
+        .remove(&guild_id);
+    data.get::<SkipVotesMap>()
+        .expect("Expected SkipVotesMap in TypeMap")
+        .write()
+        .await
+        .remove(&guild_id);
+    data.get::<CurrentTrackMap>()
+        .expect("Expected CurrentTrackMap in TypeMap")
+        .write()
+        .await
+        .remove(&guild_id);
+}
+
+/// Moves the guild's player to the least loaded node other than the one it's
+/// on, taking the queue, position, filters and pause state along. Returns
+/// whether there was a player and a node to move it to.
+pub async fn move_player(
+    ctx: &SerenityContext,
+    songbird: &Songbird,
+    lavalink: &LavalinkNodes,
+    guild_id: u64,
+) -> Result<bool, Error> {
+    let from = match lavalink.node_of(guild_id).await {
+        Some(node) => node,
+        None => return Ok(false),
+    };
+    let to = match lavalink.least_loaded(Some(from)).await {
+        Some(node) => node,
+        None => return Ok(false),
+    };
+
+    let connection_info = match songbird.get(guild_id) {
+        Some(call) => call.lock().await.current_connection().cloned(),
+        None => None,
+    };
+    let connection_info = match connection_info {
+        Some(connection_info) => connection_info,
+        None => {
+            lavalink.guild_node_map.write().await.remove(&guild_id);
+            return Ok(false);
+        }
+    };
+
+    // Nothing is moved until the new node has a session, so a failure leaves
+    // the guild on the old one
+    let client = &lavalink.clients()[to];
+    if let Err(why) = client.create_session_with_songbird(&connection_info).await {
+        client.nodes().await.remove(&guild_id);
+        return Err(why.into());
+    }
+
+    // The old client still knows the queue even if its node is gone
+    let node = lavalink.clients()[from]
+        .nodes()
+        .await
+        .remove(&guild_id)
+        .map(|(_, node)| node);
+    lavalink.guild_node_map.write().await.insert(guild_id, to);
+    // The old node might only have been late with its stats, it must not keep
+    // playing next to the new one
+    let _ = lavalink.clients()[from].destroy(guild_id).await;
+
+    let node = match node {
+        Some(node) => node,
+        None => return Ok(true),
+    };
+    let (position, filters) = {
+        let data = ctx.data.read().await;
+        let player_position_map = data
+            .get::<PlayerPositionMap>()
+            .expect("Expected PlayerPositionMap in TypeMap")
+            .read()
+            .await;
+        let filters_map = data
+            .get::<FiltersMap>()
+            .expect("Expected FiltersMap in TypeMap")
+            .read()
+            .await;
+        (
+            estimate_position(player_position_map.get(&guild_id), node.is_paused),
+            filters_map.get(&guild_id).cloned(),
+        )
+    };
+    for (i, track) in node.queue.into_iter().enumerate() {
+        // The playing track picks up where it was
+        if i == 0 && node.now_playing.is_some() {
+            let mut play = client
+                .play(guild_id, track.track)
+                .start_time(Duration::from_millis(position));
+            if let Some(requester) = track.requester {
+                play = play.requester(requester.0);
+            }
+            play.queue().await?;
+        } else {
+            enqueue(client, guild_id, track).await?;
+        }
+    }
+    if let Some(filters) = filters {
+        apply_filters(client, guild_id, &filters).await?;
+    }
+    if node.is_paused {
+        client.pause(guild_id).await?;
+    }
+
+    Ok(true)
+}
+
+/// Members other than bots that are in the same voice channel as the bot.
+pub fn voice_listeners(guild: &Guild, bot_id: UserId) -> Vec<UserId> {
+    let bot_channel_id = match guild
+        .voice_states
+        .get(&bot_id)
+        .and_then(|voice_state| voice_state.channel_id)
+    {
+        Some(channel_id) => channel_id,
+        None => return Vec::new(),
+    };
+
+    guild
+        .voice_states
+        .values()
+        .filter(|voice_state| voice_state.channel_id == Some(bot_channel_id))
+        .map(|voice_state| voice_state.user_id)
+        .filter(|user_id| {
+            !guild
+                .members
+                .get(user_id)
+                .map_or(false, |member| member.user.bot)
+        })
+        .collect()
+}
+
+/// Pauses playback and starts the idle timer once everyone left the bot's
+/// voice channel, and resumes playback when someone comes back.
+pub async fn check_voice_listeners(
+    ctx: &SerenityContext,
+    lavalink: &LavalinkClient,
+    guild_id: GuildId,
+) -> Result<(), Error> {
+    let guild = match guild_id.to_guild_cached(ctx) {
+        Some(guild) => guild,
+        None => return Ok(()),
+    };
+    let bot_id = ctx.cache.current_user_id();
+    if !guild.voice_states.contains_key(&bot_id) {
+        return Ok(());
+    }
+    let alone = voice_listeners(&guild, bot_id).is_empty();
+
+    let is_playing = lavalink
+        .nodes()
+        .await
+        .get(&guild_id.0)
+        .map_or(false, |node| node.now_playing.is_some() && !node.is_paused);
+
+    // The maps are updated before talking to Lavalink, so a slow node doesn't
+    // hold up everything else that uses them
+    let (pause, resume) = {
+        let data = ctx.data.read().await;
+        let mut alone_paused_guilds = data
+            .get::<AlonePausedGuilds>()
+            .expect("Expected AlonePausedGuilds in TypeMap")
+            .write()
+            .await;
+        let mut idle_hash_map = data
+            .get::<IdleGuildMap>()
+            .expect("Expected IdleGuildMap in TypeMap")
+            .write()
+            .await;
+        if alone && is_playing {
+            alone_paused_guilds.insert(guild_id.0);
+            idle_hash_map.insert(guild_id.0, Instant::now());
+            (true, false)
+        } else if alone {
+            idle_hash_map.entry(guild_id.0).or_insert_with(Instant::now);
+            (false, false)
+        } else if alone_paused_guilds.remove(&guild_id.0) {
+            idle_hash_map.remove(&guild_id.0);
+            (false, true)
+        } else {
+            (false, false)
+        }
+    };
+    if pause {
+        lavalink.pause(guild_id.0).await?;
+    } else if resume {
+        lavalink.resume(guild_id.0).await?;
+    }
+
+    Ok(())
+}
+
+/// Fills in the guild's history from the database, unless it's already known.
+pub async fn load_history(
+    history_map: &HistoryHashMap,
+    pool: &PgPool,
+    guild_id: u64,
+) -> anyhow::Result<()> {
+    if history_map.read().await.contains_key(&guild_id) {
+        return Ok(());
+    }
+
+    let history = database::Guild::new(pool, guild_id as i64)
+        .get_history()
+        .await?
+        .into_iter()
+        .map(TrackQueue::from)
+        .collect();
+    let mut history_map = history_map.write().await;
+    history_map.entry(guild_id).or_insert(history);
+    Ok(())
+}
+
+/// Appends an already resolved track to the back of the queue, keeping its
+/// original requester and start time.
+pub async fn enqueue(
+    client: &LavalinkClient,
+    guild_id: u64,
+    track: TrackQueue,
+) -> LavalinkResult<()> {
+    let mut play = client.play(guild_id, track.track);
+    if let Some(requester) = track.requester {
+        play = play.requester(requester.0);
+    }
+    if track.start_time > 0 {
+        play = play.start_time(Duration::from_millis(track.start_time));
+    }
+    play.queue().await
+}
+
+/// Puts an already resolved track at the head of the queue, right after the
+/// track that is currently playing.
+pub async fn enqueue_front(
+    client: &LavalinkClient,
+    guild_id: u64,
+    track: TrackQueue,
+) -> LavalinkResult<()> {
+    let track = match client.nodes().await.get_mut(&guild_id) {
+        // The first queue entry is the playing track, if there is one
+        Some(mut node) if !node.queue.is_empty() => {
+            let index = if node.now_playing.is_some() { 1 } else { 0 };
+            node.queue.insert(index, track);
+            return Ok(());
+        }
+        // An empty queue has to go through the regular path so playback gets
+        // started
+        _ => track,
+    };
+    enqueue(client, guild_id, track).await
+}
+
+/// Spreads the last `count` tracks of the queue, the ones that were just
+/// queued, between the tracks of the other requesters in a round-robin order.
+/// Tracks that were already queued keep their order.
+pub async fn interleave_queued_tracks(client: &LavalinkClient, guild_id: u64, count: usize) {
+    if let Some(mut node) = client.nodes().await.get_mut(&guild_id) {
+        let start = usize::from(node.now_playing.is_some());
+        if node.queue.len() < start + count {
+            return;
+        }
+        let new_tracks = node.queue.split_off(node.queue.len() - count);
+        for track in new_tracks {
+            let index = start + fair_queue_index(&node.queue[start..], &track);
+            node.queue.insert(index, track);
+        }
+    }
+}
+
+/// Finds where a track goes in a fair queue: before the first track that
+/// comes from a later round than its own, where a track's round is the amount
+/// of tracks its requester has ahead of it.
+fn fair_queue_index(upcoming: &[TrackQueue], track: &TrackQueue) -> usize {
+    let mut rounds: HashMap<Option<u64>, usize> = HashMap::new();
+    let track_rounds: Vec<usize> = upcoming
+        .iter()
+        .map(|queued| {
+            let round = rounds
+                .entry(queued.requester.map(|requester| requester.0))
+                .or_default();
+            *round += 1;
+            *round - 1
+        })
+        .collect();
+    let round = rounds
+        .get(&track.requester.map(|requester| requester.0))
+        .copied()
+        .unwrap_or_default();
+    track_rounds
+        .iter()
+        .position(|queued_round| *queued_round > round)
+        .unwrap_or(upcoming.len())
+}
+
+const MAX_TRACK_RETRIES: u8 = 2;
+// Lavalink reports stats every minute
+const NODE_STATS_TIMEOUT: Duration = Duration::from_secs(90);
+const AUTOPLAY_RECENT_TRACKS: usize = 20;
 
 #[async_trait]
 impl LavalinkEventHandler for LavalinkHandler {
@@ -46,38 +1135,109 @@ impl LavalinkEventHandler for LavalinkHandler {
         lava_client: lavalink_rs::LavalinkClient,
         event: lavalink_rs::model::Stats,
     ) {
-        let guild_idle_map = self.guild_idle_map.read().await;
-        for (guild_id, instant) in guild_idle_map.iter() {
-            let elapsed = instant.elapsed();
-            if elapsed > MAX_IDLE {
-                let _ = self.songbird.remove(*guild_id).await;
-                let _ = lava_client.destroy(*guild_id).await;
-                {
-                    let mut guild_idle_map = self.guild_idle_map.write().await;
-                    guild_idle_map.remove(guild_id);
+        {
+            let mut node_stats = self.node_stats.write().await;
+            node_stats.insert(self.node, (event.clone(), Instant::now()));
+        }
+
+        // Every node takes care of the guilds whose player it has, the first
+        // one also of the guilds without a player
+        let idle_guilds: Vec<(u64, Instant)> = {
+            let guild_node_map = self.guild_node_map.read().await;
+            self.guild_idle_map
+                .read()
+                .await
+                .iter()
+                .filter(|(guild_id, _)| {
+                    guild_node_map
+                        .get(guild_id)
+                        .map_or(self.node == 0, |node| *node == self.node)
+                })
+                .map(|(guild_id, idle_since)| (*guild_id, *idle_since))
+                .collect()
+        };
+        for (guild_id, idle_since) in idle_guilds {
+            let settings = match database::Guild::new(&self.pool, guild_id as i64)
+                .get_settings()
+                .await
+            {
+                Ok(settings) => settings,
+                Err(why) => {
+                    error!("error getting guild settings {:?}", why);
+                    database::GuildSettings::default()
                 }
+            };
+            // 24/7 mode keeps the bot around no matter what
+            if settings.always_on
+                || idle_since.elapsed() < Duration::from_secs(settings.idle_timeout_secs as u64)
+            {
+                continue;
             }
+
+            let _ = self.songbird.remove(guild_id).await;
+            let _ = lava_client.destroy(guild_id).await;
+            self.guild_node_map.write().await.remove(&guild_id);
+            clear_player_state(&*self.data.read().await, guild_id).await;
         }
-        info!("{:?}", event)
+        debug!("{:?}", event)
     }
 
     async fn player_update(
         &self,
-        _client: lavalink_rs::LavalinkClient,
+        client: lavalink_rs::LavalinkClient,
         event: lavalink_rs::model::PlayerUpdate,
     ) {
+        let guild_id = event.guild_id.0;
+        {
+            let mut player_position_map = self.guild_player_position_map.write().await;
+            player_position_map.insert(guild_id, (event.state.position, Instant::now()));
+        }
+        self.update_now_playing(&client, guild_id, false).await;
         debug!("{:?}", event)
     }
 
     async fn track_start(
         &self,
-        _client: lavalink_rs::LavalinkClient,
+        client: lavalink_rs::LavalinkClient,
         event: lavalink_rs::model::TrackStart,
     ) {
         {
             let mut idle_time_map = self.guild_idle_map.write().await;
-            idle_time_map.insert(event.guild_id.0, tokio::time::Instant::now());
+            idle_time_map.remove(&event.guild_id.0);
+        }
+        {
+            // Votes only count for the track they were cast on
+            let mut skip_votes_map = self.guild_skip_votes_map.write().await;
+            skip_votes_map.remove(&event.guild_id.0);
+        }
+        let now_playing = client
+            .nodes()
+            .await
+            .get(&event.guild_id.0)
+            .and_then(|node| node.now_playing.clone());
+        if let Some(now_playing) = now_playing {
+            {
+                let mut player_position_map = self.guild_player_position_map.write().await;
+                player_position_map
+                    .insert(event.guild_id.0, (now_playing.start_time, Instant::now()));
+            }
+            self.record_history(event.guild_id.0, &now_playing).await;
+            let mut current_track_map = self.guild_current_track_map.write().await;
+            current_track_map.insert(event.guild_id.0, now_playing);
+        }
+        let filters = self
+            .guild_filters_map
+            .read()
+            .await
+            .get(&event.guild_id.0)
+            .cloned();
+        if let Some(filters) = filters {
+            if let Err(e) = apply_filters(&client, event.guild_id.0, &filters).await {
+                error!("Failed to apply audio filters: {}", e);
+            }
         }
+        self.update_now_playing(&client, event.guild_id.0, true)
+            .await;
         info!("{:?}", event)
     }
 
@@ -87,68 +1247,107 @@ impl LavalinkEventHandler for LavalinkHandler {
         event: lavalink_rs::model::TrackFinish,
     ) {
         let guild_id = GuildId(event.guild_id.0);
-        if let Some(node) = client.nodes().await.get(&guild_id.0) {
-            if let Some(next_track) = node.queue.first() {
-                let last_message_map = self.guild_last_message_map.read().await;
-                if let Some(channel_id) = last_message_map.get(&guild_id.0) {
-                    if let Ok(channel) = self.http.get_channel(channel_id.0).await {
-                        match channel {
-                            Channel::Guild(guild_channel) => {
-                                let track_info = next_track.track.info.as_ref().unwrap();
-
-                                let _ = guild_channel
-                                    .send_message(&self.http, |m| {
-                                        m.embed(|e| {
-                                            e.title("Now Playing")
-                                                .field(
-                                                    "Track:",
-                                                    format!(
-                                                        "[{}]({})",
-                                                        chop_str(
-                                                            track_info.title.as_str(),
-                                                            MAX_SINGLE_ENTRY_LENGTH
-                                                        ),
-                                                        track_info.uri,
-                                                    ),
-                                                    false,
-                                                )
-                                                .field(
-                                                    "Duration:",
-                                                    display_time_span(track_info.length),
-                                                    true,
-                                                )
-                                                .field(
-                                                    "Requested By:",
-                                                    UserId(
-                                                        next_track
-                                                            .requester
-                                                            .expect(
-                                                                "Expected a requester associated \
-																 with a playing track",
-                                                            )
-                                                            .0,
-                                                    )
-                                                    .mention(),
-                                                    true,
-                                                )
-                                        })
-                                    })
-                                    .await;
-                            }
-                            _ => {}
-                        }
-                    }
+        let loop_mode = self
+            .guild_loop_mode_map
+            .read()
+            .await
+            .get(&guild_id.0)
+            .copied()
+            .unwrap_or_default();
+
+        if event.reason == "FINISHED" {
+            let mut track_retries_map = self.guild_track_retries_map.write().await;
+            track_retries_map.remove(&guild_id.0);
+        }
+
+        let finished_track = {
+            let current_track_map = self.guild_current_track_map.read().await;
+            current_track_map.get(&guild_id.0).cloned()
+        };
+        if let Some(track) = &finished_track {
+            self.record_play(&client, guild_id.0, track, event.reason == "FINISHED")
+                .await;
+        }
+
+        // A track that got replaced is out of the picture, even if it failed
+        let failure = {
+            let mut track_failures_map = self.guild_track_failures_map.write().await;
+            track_failures_map.remove(&guild_id.0)
+        };
+        if let (Some(error), Some(track), true) =
+            (failure, &finished_track, event.reason != "REPLACED")
+        {
+            if self
+                .handle_failed_track(&client, guild_id.0, track.clone(), &error)
+                .await
+            {
+                return;
+            }
+        }
+
+        // Only tracks that ran to the end are looped, skipping or stopping
+        // playback should not bring them back
+        if event.reason == "FINISHED" && loop_mode != LoopMode::Off {
+            if let Some(track) = finished_track {
+                let result = match loop_mode {
+                    LoopMode::Track => enqueue_front(&client, guild_id.0, track).await,
+                    _ => enqueue(&client, guild_id.0, track).await,
+                };
+                if let Err(e) = result {
+                    error!("Failed to re-queue looped track: {}", e);
+                }
+            }
+        }
+
+        let queue_finished = client
+            .nodes()
+            .await
+            .get(&guild_id.0)
+            .map_or(true, |node| node.queue.is_empty());
+        if !queue_finished {
+            return;
+        }
+
+        // Same as with looping, autoplay only kicks in when the music ran out
+        // on its own
+        if event.reason == "FINISHED" && self.autoplay_enabled(guild_id.0).await {
+            if let Some(track) = self.find_autoplay_track(&client, guild_id.0).await {
+                match enqueue(&client, guild_id.0, track).await {
+                    Ok(_) => return,
+                    Err(e) => error!("Failed to queue autoplay track: {}", e),
                 }
             }
         }
+
+        {
+            let mut idle_time_map = self.guild_idle_map.write().await;
+            idle_time_map.insert(guild_id.0, Instant::now());
+        }
+        self.finish_now_playing(guild_id.0).await;
     }
 
     async fn websocket_closed(
         &self,
-        _client: lavalink_rs::LavalinkClient,
+        client: lavalink_rs::LavalinkClient,
         event: lavalink_rs::model::WebSocketClosed,
     ) {
-        info!("{:?}", event)
+        info!("{:?}", event);
+
+        // Discord allows connecting to the voice channel again after these,
+        // other codes mean the bot was disconnected on purpose
+        if !matches!(event.code, 1006 | 4006 | 4009 | 4015) {
+            return;
+        }
+        let guild_id = event.guild_id.0;
+        let channel_id = match self.songbird.get(guild_id) {
+            Some(call) => call.lock().await.current_channel(),
+            None => None,
+        };
+        if let Some(channel_id) = channel_id {
+            if let Err(e) = connect_voice(&self.songbird, &client, guild_id, channel_id.0).await {
+                error!("Failed to reconnect to voice in guild {}: {}", guild_id, e);
+            }
+        }
     }
 
     async fn player_destroyed(
@@ -164,6 +1363,88 @@ impl LavalinkEventHandler for LavalinkHandler {
         _client: lavalink_rs::LavalinkClient,
         event: lavalink_rs::model::TrackException,
     ) {
-        info!("{:?}", event)
+        info!("{:?}", event);
+        // Lavalink ends the track right after, `track_finish` takes it from there
+        let mut track_failures_map = self.guild_track_failures_map.write().await;
+        track_failures_map.insert(event.guild_id.0, event.error);
+    }
+
+    async fn track_stuck(
+        &self,
+        client: lavalink_rs::LavalinkClient,
+        event: lavalink_rs::model::TrackStuck,
+    ) {
+        info!("{:?}", event);
+        {
+            let mut track_failures_map = self.guild_track_failures_map.write().await;
+            track_failures_map.insert(event.guild_id.0, "The track got stuck.".to_owned());
+        }
+        // A stuck track doesn't end by itself, stopping it lets `track_finish`
+        // take it from there
+        if let Err(e) = client.stop(event.guild_id.0).await {
+            error!("Failed to stop the stuck track: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lavalink_rs::model::UserId as LavalinkUserId;
+
+    use super::*;
+
+    fn queued(requester: Option<u64>) -> TrackQueue {
+        TrackQueue {
+            track: Track {
+                track: String::new(),
+                info: None,
+            },
+            start_time: 0,
+            end_time: None,
+            requester: requester.map(LavalinkUserId),
+        }
+    }
+
+    fn requesters(queue: &[TrackQueue]) -> Vec<Option<u64>> {
+        queue
+            .iter()
+            .map(|queued| queued.requester.map(|requester| requester.0))
+            .collect()
+    }
+
+    fn insert_fairly(queue: &mut Vec<TrackQueue>, requester: Option<u64>) {
+        let track = queued(requester);
+        let index = fair_queue_index(queue, &track);
+        queue.insert(index, track);
+    }
+
+    #[test]
+    fn queues_single_requester_in_order() {
+        let mut queue = Vec::new();
+        for _ in 0..3 {
+            insert_fairly(&mut queue, Some(1));
+        }
+        assert_eq!(requesters(&queue), vec![Some(1); 3]);
+        assert_eq!(fair_queue_index(&queue, &queued(Some(1))), 3);
+    }
+
+    #[test]
+    fn interleaves_requesters() {
+        let mut queue = vec![queued(Some(1)), queued(Some(1)), queued(Some(1))];
+        insert_fairly(&mut queue, Some(2));
+        insert_fairly(&mut queue, Some(2));
+        insert_fairly(&mut queue, Some(3));
+        assert_eq!(
+            requesters(&queue),
+            vec![Some(1), Some(2), Some(3), Some(1), Some(2), Some(1)]
+        );
+    }
+
+    #[test]
+    fn treats_unknown_requesters_as_one() {
+        let mut queue = vec![queued(None), queued(None)];
+        insert_fairly(&mut queue, Some(1));
+        insert_fairly(&mut queue, None);
+        assert_eq!(requesters(&queue), vec![None, Some(1), None, None]);
     }
 }
@@ -30,9 +30,17 @@ use tracing_log::env_logger;
 use tracing_subscriber::{EnvFilter, FmtSubscriber};
 
 use crate::{
-    data::{Data, IdleGuildMap, LastMessageMap, PgPoolContainer, Uptime},
-    lavalink::LavalinkHandler,
-    types::{IdleHashMap, LastMessageHashMap},
+    data::{
+        AlonePausedGuilds, CurrentTrackMap, Data, FiltersMap, HistoryMap, IdleGuildMap,
+        LastMessageMap, LoopModeMap, NowPlayingMessageMap, PgPoolContainer, PlayerPositionMap,
+        SkipVotesMap, Uptime,
+    },
+    lavalink::{LavalinkHandler, LavalinkNodes},
+    types::{
+        AlonePausedGuildSet, CurrentTrackHashMap, FiltersHashMap, GuildNodeHashMap,
+        HistoryHashMap, IdleHashMap, LastMessageHashMap, LoopModeHashMap, NodeStatsHashMap,
+        NowPlayingMessageHashMap, PlayerPositionHashMap, SkipVotesHashMap,
+    },
 };
 
 #[tokio::main]
@@ -69,6 +77,7 @@ async fn main() -> anyhow::Result<()> {
         .await
         .with_context(|| "Failed to connect to database".to_owned())?;
     let db_pool_clone = db_pool.clone();
+    let db_pool_handler_clone = db_pool.clone();
 
     info!("Database client started");
 
@@ -78,6 +87,32 @@ async fn main() -> anyhow::Result<()> {
     let idle_hash_map: IdleHashMap = Arc::new(RwLock::new(HashMap::new()));
     let idle_hash_map_clone = idle_hash_map.clone();
 
+    let loop_mode_map: LoopModeHashMap = Arc::new(RwLock::new(HashMap::new()));
+    let loop_mode_map_clone = loop_mode_map.clone();
+
+    let player_position_map: PlayerPositionHashMap = Arc::new(RwLock::new(HashMap::new()));
+    let player_position_map_clone = player_position_map.clone();
+
+    let filters_map: FiltersHashMap = Arc::new(RwLock::new(HashMap::new()));
+    let filters_map_clone = filters_map.clone();
+
+    let now_playing_message_map: NowPlayingMessageHashMap = Arc::new(RwLock::new(HashMap::new()));
+    let now_playing_message_map_clone = now_playing_message_map.clone();
+
+    let skip_votes_map: SkipVotesHashMap = Arc::new(RwLock::new(HashMap::new()));
+    let skip_votes_map_clone = skip_votes_map.clone();
+
+    let history_map: HistoryHashMap = Arc::new(RwLock::new(HashMap::new()));
+    let history_map_clone = history_map.clone();
+
+    let alone_paused_guilds: AlonePausedGuildSet = Arc::new(RwLock::new(HashSet::new()));
+
+    let current_track_map: CurrentTrackHashMap = Arc::new(RwLock::new(HashMap::new()));
+    let current_track_map_clone = current_track_map.clone();
+
+    let node_stats_map: NodeStatsHashMap = Arc::new(RwLock::new(HashMap::new()));
+    let guild_node_map: GuildNodeHashMap = Arc::new(RwLock::new(HashMap::new()));
+
     info!("Lavalink client started");
 
     let songbird = Songbird::serenity();
@@ -86,19 +121,54 @@ async fn main() -> anyhow::Result<()> {
     get_framework_builder(bot_token, owners)
         .user_data_setup(move |ctx, _ready, _framework| {
             Box::pin(async move {
-                let lavalink_host = get_env::<String>("LAVALINK_HOST")?;
-                let lavalink_password = get_env::<String>("LAVALINK_PASSWORD")?;
-                let lavalink = LavalinkClient::builder(bot_id.0)
-                    .set_host(lavalink_host)
-                    .set_password(lavalink_password)
-                    .build(LavalinkHandler::new(
-                        last_message_map_clone,
-                        idle_hash_map_clone,
-                        ctx.http.clone(),
-                        songbird_clone.clone(),
-                    ))
-                    .await
-                    .with_context(|| "Failed to start the Lavalink client")?;
+                // Several nodes are separated by commas, they either share a
+                // password or have one each
+                let lavalink_hosts = get_env::<String>("LAVALINK_HOST")?;
+                let lavalink_passwords = get_env::<String>("LAVALINK_PASSWORD")?;
+                let lavalink_passwords: Vec<&str> =
+                    lavalink_passwords.split(',').map(str::trim).collect();
+
+                let mut lavalink_clients = Vec::new();
+                for (node, host) in lavalink_hosts.split(',').map(str::trim).enumerate() {
+                    let password = lavalink_passwords
+                        .get(node)
+                        .or_else(|| lavalink_passwords.last())
+                        .unwrap();
+                    let mut builder = LavalinkClient::builder(bot_id.0);
+                    match host
+                        .rsplit_once(':')
+                        .and_then(|(host, port)| port.parse::<u16>().ok().map(|port| (host, port)))
+                    {
+                        Some((host, port)) => builder.set_host(host).set_port(port),
+                        None => builder.set_host(host),
+                    };
+                    let client = builder
+                        .set_password(*password)
+                        .build(LavalinkHandler::new(
+                            node,
+                            last_message_map_clone.clone(),
+                            idle_hash_map_clone.clone(),
+                            loop_mode_map_clone.clone(),
+                            player_position_map_clone.clone(),
+                            filters_map_clone.clone(),
+                            now_playing_message_map_clone.clone(),
+                            skip_votes_map_clone.clone(),
+                            history_map_clone.clone(),
+                            current_track_map_clone.clone(),
+                            node_stats_map.clone(),
+                            guild_node_map.clone(),
+                            db_pool_handler_clone.clone(),
+                            ctx.http.clone(),
+                            songbird_clone.clone(),
+                            ctx.data.clone(),
+                        ))
+                        .await
+                        .with_context(|| {
+                            format!("Failed to start the Lavalink client for {}", host)
+                        })?;
+                    lavalink_clients.push(client);
+                }
+                let lavalink = LavalinkNodes::new(lavalink_clients, node_stats_map, guild_node_map);
                 Ok(Data::new(songbird_clone, lavalink))
             })
         })
@@ -115,6 +185,14 @@ async fn main() -> anyhow::Result<()> {
                 .type_map_insert::<Uptime>(Instant::now())
                 .type_map_insert::<LastMessageMap>(last_message_map)
                 .type_map_insert::<IdleGuildMap>(idle_hash_map)
+                .type_map_insert::<LoopModeMap>(loop_mode_map)
+                .type_map_insert::<PlayerPositionMap>(player_position_map)
+                .type_map_insert::<FiltersMap>(filters_map)
+                .type_map_insert::<NowPlayingMessageMap>(now_playing_message_map)
+                .type_map_insert::<SkipVotesMap>(skip_votes_map)
+                .type_map_insert::<HistoryMap>(history_map)
+                .type_map_insert::<AlonePausedGuilds>(alone_paused_guilds)
+                .type_map_insert::<CurrentTrackMap>(current_track_map)
         })
         .run()
         .await
@@ -1,11 +1,23 @@
 use std::sync::Arc;
 
-use poise::serenity_prelude::{Activity, OnlineStatus};
+use lavalink_rs::model::TrackQueue;
+use poise::serenity_prelude::{Activity, ChannelId, OnlineStatus};
 use rand::seq::SliceRandom;
+use songbird::Songbird;
+use sqlx::PgPool;
 use tokio::time::Duration;
-use tracing::info;
+use tracing::{error, info};
 
-use crate::{constants::STATUSES, types::SerenityContext};
+use crate::{
+	constants::STATUSES,
+	data::{LastMessageMap, PgPoolContainer, PlayerPositionMap},
+	database::{delete_stale_music_queues, get_music_queues, Guild, MusicQueue, StoredTrack},
+	lavalink::{enqueue, estimate_position, join_internal, move_player, LavalinkNodes},
+	types::SerenityContext,
+};
+
+const QUEUE_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(30);
+const NODE_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
 
 async fn status_update(ctx: Arc<SerenityContext>) {
 	let random_status = STATUSES.choose(&mut rand::thread_rng()).unwrap();
@@ -20,6 +32,173 @@ async fn status_update(ctx: Arc<SerenityContext>) {
 	info!("Status update done");
 }
 
+// Saves the queue of every guild with an active player, and drops the saved
+// queues of the ones that stopped playing since the last snapshot.
+async fn snapshot_queues(
+	ctx: &SerenityContext,
+	songbird: &Songbird,
+	lavalink: &LavalinkNodes,
+) -> anyhow::Result<()> {
+	let mut queues: Vec<(u64, Vec<TrackQueue>, bool, bool)> = Vec::new();
+	for client in lavalink.clients() {
+		queues.extend(
+			client
+				.nodes()
+				.await
+				.iter()
+				.filter(|node| !node.queue.is_empty())
+				.map(|node| {
+					(
+						*node.key(),
+						node.queue.clone(),
+						node.now_playing.is_some(),
+						node.is_paused,
+					)
+				}),
+		);
+	}
+
+	let db = {
+		let data = ctx.data.read().await;
+		data.get::<PgPoolContainer>().unwrap().clone()
+	};
+
+	let mut active_guild_ids = Vec::with_capacity(queues.len());
+	for (guild_id, queue, is_playing, is_paused) in queues {
+		let voice_channel_id = match songbird.get(guild_id) {
+			Some(call) => call.lock().await.current_channel(),
+			None => None,
+		};
+		let voice_channel_id = match voice_channel_id {
+			Some(channel_id) => channel_id.0 as i64,
+			None => continue,
+		};
+
+		let (text_channel_id, position) = {
+			let data = ctx.data.read().await;
+			let last_message_map = data.get::<LastMessageMap>().unwrap().read().await;
+			let player_position_map = data.get::<PlayerPositionMap>().unwrap().read().await;
+			let position = if is_playing {
+				estimate_position(player_position_map.get(&guild_id), is_paused)
+			} else {
+				0
+			};
+			(
+				last_message_map.get(&guild_id).map(|c| c.0 as i64),
+				position,
+			)
+		};
+
+		let tracks: Vec<StoredTrack> = queue.iter().map(StoredTrack::from).collect();
+		if let Err(why) = Guild::new(&db, guild_id as i64)
+			.save_music_queue(voice_channel_id, text_channel_id, position as i64, &tracks)
+			.await
+		{
+			error!("error saving music queue {:?}", why);
+		}
+		active_guild_ids.push(guild_id as i64);
+	}
+
+	delete_stale_music_queues(&db, &active_guild_ids).await?;
+
+	Ok(())
+}
+
+// Rejoins the voice channel and re-queues the saved queue of one guild.
+async fn restore_queue(
+	ctx: &SerenityContext,
+	songbird: &Songbird,
+	lavalink: &LavalinkNodes,
+	db: &PgPool,
+	queue: &MusicQueue,
+) -> anyhow::Result<()> {
+	let guild_id = queue.guild_id as u64;
+	let tracks = Guild::new(db, queue.guild_id)
+		.get_music_queue_tracks()
+		.await?;
+	if tracks.is_empty() {
+		return Ok(());
+	}
+
+	join_internal(
+		ctx,
+		songbird,
+		lavalink,
+		guild_id,
+		queue.voice_channel_id as u64,
+	)
+	.await
+	.map_err(|why| anyhow::anyhow!("error rejoining voice channel {:?}", why))?;
+
+	if let Some(text_channel_id) = queue.text_channel_id {
+		let data = ctx.data.read().await;
+		let mut last_message_map = data.get::<LastMessageMap>().unwrap().write().await;
+		last_message_map.insert(guild_id, ChannelId(text_channel_id as u64));
+	}
+
+	let client = lavalink.client(guild_id).await;
+	for (i, track) in tracks.into_iter().enumerate() {
+		let track = TrackQueue::from(track);
+		// The first track picks up where it left off
+		if i == 0 && queue.position > 0 {
+			let mut play = client
+				.play(guild_id, track.track)
+				.start_time(Duration::from_millis(queue.position as u64));
+			if let Some(requester) = track.requester {
+				play = play.requester(requester.0);
+			}
+			play.queue().await?;
+		} else {
+			enqueue(&client, guild_id, track).await?;
+		}
+	}
+	info!("Restored music queue of guild {}", guild_id);
+
+	Ok(())
+}
+
+// Rejoins the voice channels and re-queues the queues that were saved before
+// the bot went offline. A guild that fails to restore doesn't hold up the
+// others.
+async fn restore_queues(
+	ctx: &SerenityContext,
+	songbird: &Songbird,
+	lavalink: &LavalinkNodes,
+) -> anyhow::Result<()> {
+	let db = {
+		let data = ctx.data.read().await;
+		data.get::<PgPoolContainer>().unwrap().clone()
+	};
+
+	for queue in get_music_queues(&db).await? {
+		if let Err(why) = restore_queue(ctx, songbird, lavalink, &db, &queue).await {
+			error!(
+				"error restoring music queue of guild {} {:?}",
+				queue.guild_id, why
+			);
+		}
+	}
+
+	Ok(())
+}
+
+// Moves the players of nodes that stopped reporting stats over to the nodes
+// that are still up.
+async fn fail_over_nodes(ctx: &SerenityContext, songbird: &Songbird, lavalink: &LavalinkNodes) {
+	for node in 0..lavalink.clients().len() {
+		if lavalink.is_healthy(node).await {
+			continue;
+		}
+		for guild_id in lavalink.guilds_on(node).await {
+			match move_player(ctx, songbird, lavalink, guild_id).await {
+				Ok(true) => info!("Moved the player of guild {} off node {}", guild_id, node),
+				Ok(false) => {}
+				Err(why) => error!("error moving player {:?}", why),
+			}
+		}
+	}
+}
+
 pub async fn start_services(ctx: Arc<SerenityContext>) {
 	let ctx_clone1 = Arc::clone(&ctx);
 	tokio::spawn(async move {
@@ -29,3 +208,33 @@ pub async fn start_services(ctx: Arc<SerenityContext>) {
 		}
 	});
 }
+
+pub async fn start_queue_persistence(
+	ctx: Arc<SerenityContext>,
+	songbird: Arc<Songbird>,
+	lavalink: LavalinkNodes,
+) {
+	let ctx_clone = Arc::clone(&ctx);
+	let songbird_clone = Arc::clone(&songbird);
+	let lavalink_clone = lavalink.clone();
+	tokio::spawn(async move {
+		loop {
+			tokio::time::sleep(NODE_HEALTH_CHECK_INTERVAL).await;
+			fail_over_nodes(&ctx_clone, &songbird_clone, &lavalink_clone).await;
+		}
+	});
+
+	tokio::spawn(async move {
+		// Restoring has to come first, the first snapshot would otherwise drop
+		// every saved queue
+		if let Err(why) = restore_queues(&ctx, &songbird, &lavalink).await {
+			error!("error restoring music queues {:?}", why);
+		}
+		loop {
+			tokio::time::sleep(QUEUE_SNAPSHOT_INTERVAL).await;
+			if let Err(why) = snapshot_queues(&ctx, &songbird, &lavalink).await {
+				error!("error saving music queues {:?}", why);
+			}
+		}
+	});
+}
@@ -1,12 +1,20 @@
-use std::{collections::HashMap, error, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    error,
+    sync::Arc,
+};
 
+use lavalink_rs::model::{Stats, TrackQueue};
 use poise::{
     serenity,
     serenity_prelude::{ChannelId, RwLock},
 };
 use tokio::time::Instant;
 
-use crate::data::Data;
+use crate::{
+    data::Data,
+    lavalink::{AudioFilters, LoopMode, NowPlayingMessage},
+};
 
 pub type Error = Box<dyn error::Error + Send + Sync>;
 pub type PoiseContext<'a> = poise::Context<'a, Data, Error>;
@@ -14,3 +22,22 @@ pub type SerenityContext = serenity::client::Context;
 
 pub type LastMessageHashMap = Arc<RwLock<HashMap<u64, ChannelId>>>;
 pub type IdleHashMap = Arc<RwLock<HashMap<u64, Instant>>>;
+pub type LoopModeHashMap = Arc<RwLock<HashMap<u64, LoopMode>>>;
+// Last known playback position of each player in milliseconds, and when it
+// was reported
+pub type PlayerPositionHashMap = Arc<RwLock<HashMap<u64, (u64, Instant)>>>;
+pub type FiltersHashMap = Arc<RwLock<HashMap<u64, AudioFilters>>>;
+pub type NowPlayingMessageHashMap = Arc<RwLock<HashMap<u64, NowPlayingMessage>>>;
+// Users who voted to skip the playing track
+pub type SkipVotesHashMap = Arc<RwLock<HashMap<u64, HashSet<u64>>>>;
+// Guilds whose player was paused because everyone left the voice channel
+pub type AlonePausedGuildSet = Arc<RwLock<HashSet<u64>>>;
+// Recently played tracks of each guild, newest first
+pub type HistoryHashMap = Arc<RwLock<HashMap<u64, VecDeque<TrackQueue>>>>;
+// The track each guild's player is playing, Lavalink drops finished tracks
+// from the queue before the end of a track is handled
+pub type CurrentTrackHashMap = Arc<RwLock<HashMap<u64, TrackQueue>>>;
+// Latest stats reported by each Lavalink node, and when they were reported
+pub type NodeStatsHashMap = Arc<RwLock<HashMap<usize, (Stats, Instant)>>>;
+// Lavalink node each guild's player lives on
+pub type GuildNodeHashMap = Arc<RwLock<HashMap<u64, usize>>>;
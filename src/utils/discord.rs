@@ -1,7 +1,9 @@
+use std::borrow::Cow;
+
 use poise::{
 	send_reply,
-	serenity::builder::CreateEmbed,
-	serenity_prelude::{ChannelId, Colour, Guild, SerenityError},
+	serenity::builder::{CreateComponents, CreateEmbed},
+	serenity_prelude::{AttachmentType, ChannelId, Colour, Guild, SerenityError},
 	ReplyHandle,
 };
 use serde::Serialize;
@@ -15,6 +17,8 @@ use crate::{
 	Uptime,
 };
 
+pub const EMBED_COLOUR: Colour = Colour(0xbf5c4e);
+
 #[derive(Clone, Serialize)]
 pub struct MetaInfoResult {
 	pub uptime: String,
@@ -95,7 +99,7 @@ pub async fn reply<S: ToString>(
 	msg: S,
 ) -> Result<ReplyHandle<'_>, SerenityError> {
 	send_reply(ctx, |m| {
-		m.embed(|e| e.colour(Colour(0xbf5c4e)).description(msg))
+		m.embed(|e| e.colour(EMBED_COLOUR).description(msg))
 	})
 	.await
 }
@@ -111,7 +115,37 @@ pub async fn reply_embed(
 	ctx: PoiseContext<'_>,
 	embed: impl FnOnce(&mut CreateEmbed) -> &mut CreateEmbed,
 ) -> Result<ReplyHandle<'_>, SerenityError> {
-	send_reply(ctx, |m| m.embed(|e| embed(e.colour(Colour(0xbf5c4e))))).await
+	send_reply(ctx, |m| m.embed(|e| embed(e.colour(EMBED_COLOUR)))).await
+}
+
+pub async fn reply_embed_components(
+	ctx: PoiseContext<'_>,
+	embed: impl FnOnce(&mut CreateEmbed) -> &mut CreateEmbed,
+	components: impl FnOnce(&mut CreateComponents) -> &mut CreateComponents,
+) -> Result<ReplyHandle<'_>, SerenityError> {
+	send_reply(ctx, |m| {
+		m.embed(|e| embed(e.colour(EMBED_COLOUR)))
+			.components(components)
+	})
+	.await
+}
+
+/// Sends a file to the channel the command was used in, followed by a reply.
+pub async fn reply_file<S: ToString>(
+	ctx: PoiseContext<'_>,
+	msg: S,
+	filename: String,
+	data: Vec<u8>,
+) -> Result<ReplyHandle<'_>, SerenityError> {
+	ctx.channel_id()
+		.send_message(ctx.discord(), |m| {
+			m.add_file(AttachmentType::Bytes {
+				data: Cow::from(data),
+				filename,
+			})
+		})
+		.await?;
+	reply(ctx, msg).await
 }
 
 pub async fn guild_check(ctx: PoiseContext<'_>) -> anyhow::Result<Guild> {
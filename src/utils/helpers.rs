@@ -1,4 +1,7 @@
+use std::time::Duration;
+
 use lazy_static::lazy_static;
+use parse_duration::parse as parse_duration;
 use regex::Regex;
 
 // Capitalizes the first letter of a str.
@@ -88,3 +91,169 @@ pub fn display_time_span(millis: u64) -> String {
 		)
 	}
 }
+
+/// Parses a 1-based position (`3`) or an inclusive range of positions (`3-7`).
+pub fn parse_range(s: &str) -> Option<(usize, usize)> {
+	let (from, to) = match s.split_once('-') {
+		Some((from, to)) => (from.trim().parse().ok()?, to.trim().parse().ok()?),
+		None => {
+			let position = s.trim().parse().ok()?;
+			(position, position)
+		}
+	};
+	if from == 0 || from > to {
+		return None;
+	}
+	Some((from, to))
+}
+
+/// Renders a progress bar with a knob at `position` out of `length`.
+pub fn progress_bar(position: u64, length: u64, width: usize) -> String {
+	let filled = if length == 0 {
+		0
+	} else {
+		((position.min(length) as f64 / length as f64) * width as f64) as usize
+	};
+	let filled = filled.min(width - 1);
+	format!("{}🔘{}", "▬".repeat(filled), "─".repeat(width - filled - 1))
+}
+
+/// A time to seek to, either a position in the track or an offset from the
+/// current position.
+#[derive(Debug, PartialEq)]
+pub enum SeekTime {
+	Absolute(Duration),
+	Forward(Duration),
+	Backward(Duration),
+}
+
+/// Parses a time given as timecodes (`2:35`, `1:02:03.5`), as individual time
+/// values (`2m35s`, `2 minutes`), or a mix of both.
+pub fn parse_time(time: &str) -> Option<Duration> {
+	if time.trim().is_empty() {
+		return None;
+	}
+
+	// Timecodes are turned into milliseconds so the whole thing can be handed
+	// to the duration parser in one go
+	let mut time_prepared = String::with_capacity(time.len());
+	for word in time.split_whitespace() {
+		if word.contains(':') {
+			time_prepared.push_str(&parse_timecode(word)?.as_millis().to_string());
+			time_prepared.push_str("ms");
+		} else {
+			time_prepared.push_str(word);
+		}
+		time_prepared.push(' ');
+	}
+	parse_duration(time_prepared.as_str()).ok()
+}
+
+/// Parses a timecode with up to three colon separated parts and an optional
+/// fraction of a second, like `1:02:03.5`.
+fn parse_timecode(timecode: &str) -> Option<Duration> {
+	let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+
+	let (whole, fraction) = match timecode.split_once('.') {
+		Some((whole, fraction)) => (whole, Some(fraction)),
+		None => (timecode, None),
+	};
+	let parts: Vec<&str> = whole.split(':').collect();
+	if parts.len() > 3 {
+		return None;
+	}
+	let mut seconds: u64 = 0;
+	for part in parts {
+		if !is_number(part) {
+			return None;
+		}
+		seconds = seconds.checked_mul(60)?.checked_add(part.parse().ok()?)?;
+	}
+	let mut millis = seconds.checked_mul(1000)?;
+	if let Some(fraction) = fraction {
+		if !is_number(fraction) || fraction.len() > 3 {
+			return None;
+		}
+		millis += fraction.parse::<u64>().ok()? * 10_u64.pow(3 - fraction.len() as u32);
+	}
+	Some(Duration::from_millis(millis))
+}
+
+/// Parses a time to seek to, where a leading `+` or `-` makes it relative to
+/// the current position.
+pub fn parse_seek_time(time: &str) -> Option<SeekTime> {
+	let time = time.trim();
+	if let Some(offset) = time.strip_prefix('+') {
+		parse_time(offset).map(SeekTime::Forward)
+	} else if let Some(offset) = time.strip_prefix('-') {
+		parse_time(offset).map(SeekTime::Backward)
+	} else {
+		parse_time(time).map(SeekTime::Absolute)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parses_timecodes() {
+		assert_eq!(parse_time("2:35"), Some(Duration::from_secs(155)));
+		assert_eq!(parse_time("1:02:03"), Some(Duration::from_secs(3723)));
+		assert_eq!(parse_time("0:05.5"), Some(Duration::from_millis(5500)));
+		assert_eq!(parse_time("1:00.25"), Some(Duration::from_millis(60_250)));
+	}
+
+	#[test]
+	fn parses_time_values() {
+		assert_eq!(parse_time("2m35s"), Some(Duration::from_secs(155)));
+		assert_eq!(parse_time("2 minutes"), Some(Duration::from_secs(120)));
+		assert_eq!(parse_time("1:00 30s"), Some(Duration::from_secs(90)));
+	}
+
+	#[test]
+	fn rejects_invalid_times() {
+		assert_eq!(parse_time(""), None);
+		assert_eq!(parse_time("soon"), None);
+		assert_eq!(parse_time("1:2:3:4"), None);
+		assert_eq!(parse_time("1:"), None);
+		assert_eq!(parse_time("1:x0"), None);
+		assert_eq!(parse_time("1:00.5.5"), None);
+		assert_eq!(parse_time("1:00.1234"), None);
+	}
+
+	#[test]
+	fn parses_ranges() {
+		assert_eq!(parse_range("3"), Some((3, 3)));
+		assert_eq!(parse_range("3-7"), Some((3, 7)));
+		assert_eq!(parse_range(" 1 - 1 "), Some((1, 1)));
+	}
+
+	#[test]
+	fn rejects_invalid_ranges() {
+		assert_eq!(parse_range("7-3"), None);
+		assert_eq!(parse_range("0"), None);
+		assert_eq!(parse_range("0-3"), None);
+		assert_eq!(parse_range("-3"), None);
+		assert_eq!(parse_range("3-"), None);
+		assert_eq!(parse_range("1-99999999999999999999999"), None);
+		assert_eq!(parse_range("one"), None);
+	}
+
+	#[test]
+	fn parses_seek_times() {
+		assert_eq!(
+			parse_seek_time("1:30"),
+			Some(SeekTime::Absolute(Duration::from_secs(90)))
+		);
+		assert_eq!(
+			parse_seek_time("+30s"),
+			Some(SeekTime::Forward(Duration::from_secs(30)))
+		);
+		assert_eq!(
+			parse_seek_time(" -1:00"),
+			Some(SeekTime::Backward(Duration::from_secs(60)))
+		);
+		assert_eq!(parse_seek_time("+"), None);
+	}
+}
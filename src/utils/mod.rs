@@ -1,3 +1,4 @@
 pub mod apis;
 pub mod discord;
 pub mod helpers;
+pub mod playlist_files;
@@ -0,0 +1,164 @@
+use serde::{Deserialize, Serialize};
+
+use crate::database::StoredTrack;
+
+/// File formats queues and playlists can be exported to and imported from.
+#[derive(Clone, Copy, PartialEq)]
+pub enum PlaylistFormat {
+	M3u,
+	Json,
+}
+
+impl PlaylistFormat {
+	pub fn from_flag(json: bool) -> Self {
+		if json {
+			PlaylistFormat::Json
+		} else {
+			PlaylistFormat::M3u
+		}
+	}
+
+	/// Picks the format based on the extension of a file name, `None` if the
+	/// file isn't a playlist file.
+	pub fn from_filename(filename: &str) -> Option<Self> {
+		let (_, extension) = filename.rsplit_once('.')?;
+		match extension.to_lowercase().as_str() {
+			"m3u" | "m3u8" => Some(PlaylistFormat::M3u),
+			"json" => Some(PlaylistFormat::Json),
+			_ => None,
+		}
+	}
+
+	pub fn extension(self) -> &'static str {
+		match self {
+			PlaylistFormat::M3u => "m3u",
+			PlaylistFormat::Json => "json",
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize)]
+struct PlaylistFileTrack {
+	#[serde(default)]
+	title: String,
+	#[serde(default)]
+	author: String,
+	uri: String,
+	#[serde(default)]
+	length: u64,
+	#[serde(default)]
+	is_stream: bool,
+}
+
+/// Writes the tracks to a playlist file. Only the track URLs are needed to
+/// import the file again, the rest is there for other players and humans.
+pub fn export_playlist(format: PlaylistFormat, tracks: &[StoredTrack]) -> anyhow::Result<String> {
+	Ok(match format {
+		PlaylistFormat::M3u => {
+			let mut m3u = String::from("#EXTM3U\n");
+			for track in tracks {
+				let seconds = if track.is_stream {
+					-1
+				} else {
+					track.length / 1000
+				};
+				m3u.push_str(&format!(
+					"#EXTINF:{},{} - {}\n{}\n",
+					seconds,
+					track.author.replace('\n', " "),
+					track.title.replace('\n', " "),
+					track.uri
+				));
+			}
+			m3u
+		}
+		PlaylistFormat::Json => serde_json::to_string_pretty(
+			&tracks
+				.iter()
+				.map(|track| PlaylistFileTrack {
+					title: track.title.clone(),
+					author: track.author.clone(),
+					uri: track.uri.clone(),
+					length: track.length as u64,
+					is_stream: track.is_stream,
+				})
+				.collect::<Vec<_>>(),
+		)?,
+	})
+}
+
+/// Reads the track URLs out of a playlist file.
+pub fn import_playlist(format: PlaylistFormat, content: &str) -> anyhow::Result<Vec<String>> {
+	Ok(match format {
+		PlaylistFormat::M3u => content
+			.lines()
+			.map(str::trim)
+			.filter(|line| !line.is_empty() && !line.starts_with('#'))
+			.map(str::to_owned)
+			.collect(),
+		PlaylistFormat::Json => serde_json::from_str::<Vec<PlaylistFileTrack>>(content)?
+			.into_iter()
+			.map(|track| track.uri)
+			.collect(),
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn stored_track(title: &str, uri: &str) -> StoredTrack {
+		StoredTrack {
+			track: String::new(),
+			title: title.to_owned(),
+			author: "Author".to_owned(),
+			identifier: String::new(),
+			uri: uri.to_owned(),
+			length: 215_000,
+			is_stream: false,
+			is_seekable: true,
+			requester: None,
+		}
+	}
+
+	#[test]
+	fn skips_m3u_comments_and_blank_lines() {
+		let content = "#EXTM3U\n\n#EXTINF:215,Author - First\nhttps://a.example/1\n  \n# note\n  https://a.example/2  \r\n";
+		assert_eq!(
+			import_playlist(PlaylistFormat::M3u, content).unwrap(),
+			vec!["https://a.example/1", "https://a.example/2"]
+		);
+	}
+
+	#[test]
+	fn rejects_invalid_json() {
+		assert!(import_playlist(PlaylistFormat::Json, "not json").is_err());
+		assert!(import_playlist(PlaylistFormat::Json, r#"[{"title": "No uri"}]"#).is_err());
+		assert!(
+			import_playlist(PlaylistFormat::Json, r#"{"uri": "https://a.example/1"}"#).is_err()
+		);
+	}
+
+	#[test]
+	fn round_trips_playlists() {
+		let tracks = vec![
+			stored_track("First", "https://a.example/1"),
+			stored_track("Second\nLine", "https://a.example/2"),
+		];
+		for format in [PlaylistFormat::M3u, PlaylistFormat::Json].iter().copied() {
+			let content = export_playlist(format, &tracks).unwrap();
+			assert_eq!(
+				import_playlist(format, &content).unwrap(),
+				vec!["https://a.example/1", "https://a.example/2"]
+			);
+		}
+	}
+
+	#[test]
+	fn picks_format_from_filename() {
+		assert!(PlaylistFormat::from_filename("mix.M3U8") == Some(PlaylistFormat::M3u));
+		assert!(PlaylistFormat::from_filename("mix.json") == Some(PlaylistFormat::Json));
+		assert!(PlaylistFormat::from_filename("mix.mp3").is_none());
+		assert!(PlaylistFormat::from_filename("mix").is_none());
+	}
+}